@@ -0,0 +1,342 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Round,
+    Square,
+    // Single pixel lines without doubled corners
+    PixelPerfect,
+    Bitmap(Matrix<bool>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Dither {
+    None,
+    Dense,
+    Checker,
+    Sparse,
+}
+
+impl Dither {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Dense,
+            Self::Dense => Self::Checker,
+            Self::Checker => Self::Sparse,
+            Self::Sparse => Self::None,
+        }
+    }
+    // The pattern is anchored to the canvas so that overlapping strokes line up
+    fn includes(self, position: Vec2<i32>) -> bool {
+        let even_x = position.x.rem_euclid(2) == 0;
+        let even_y = position.y.rem_euclid(2) == 0;
+        match self {
+            Self::None => true,
+            Self::Dense => !(even_x && even_y),
+            Self::Checker => even_x == even_y,
+            Self::Sparse => even_x && even_y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Brush {
+    pub shape: Shape,
+    pub size: f32,
+    pub dither: Dither,
+}
+
+impl Brush {
+    const MIN_SIZE: f32 = 0.5;
+    const MAX_SIZE: f32 = 64.0;
+    pub fn new() -> Self {
+        Self {
+            shape: Shape::Round,
+            size: 2.0,
+            dither: Dither::None,
+        }
+    }
+    // Brushes loaded from the settings file may have been edited by hand
+    pub fn sanitize(&mut self) {
+        if let Shape::Bitmap(mask) = &self.shape {
+            let size = mask.size();
+            if size.x == 0
+                || size.y == 0
+                || size.x.checked_mul(size.y) != Some(mask.as_slice().len())
+            {
+                warn!("Ignoring malformed bitmap brush");
+                *self = Self::new();
+            }
+        }
+        self.size = if self.size.is_nan() {
+            Self::new().size
+        } else {
+            self.size.clamp(Self::MIN_SIZE, Self::MAX_SIZE)
+        };
+    }
+    pub fn grow(&mut self) {
+        let step = if self.size < 10.0 { 0.5 } else { 2.0 };
+        self.size = (self.size + step).min(Self::MAX_SIZE);
+    }
+    pub fn shrink(&mut self) {
+        let step = if self.size <= 10.0 { 0.5 } else { 2.0 };
+        self.size = (self.size - step).max(Self::MIN_SIZE);
+    }
+    pub fn next_shape(&mut self) {
+        self.shape = match self.shape {
+            Shape::Round => Shape::Square,
+            Shape::Square => Shape::PixelPerfect,
+            _ => Shape::Round,
+        };
+    }
+    fn stamp_size(&self) -> Vec2<usize> {
+        match &self.shape {
+            Shape::Bitmap(mask) => mask.size(),
+            Shape::PixelPerfect => vec2(1, 1),
+            _ => {
+                let side = ((self.size * 2.0).round() as usize).max(1);
+                vec2(side, side)
+            }
+        }
+    }
+    fn stamp_origin(&self, center: Vec2<f32>) -> Vec2<i32> {
+        (center - self.stamp_size().map(|x| x as f32) / 2.0).map(|x| x.round() as i32)
+    }
+    // Area covered by a stamp at the position, used to draw the cursor
+    pub fn outline(&self, position: Vec2<f32>) -> AABB<f32> {
+        AABB::point(self.stamp_origin(position))
+            .extend_positive(self.stamp_size().map(|x| x as i32))
+            .map(|x| x as f32)
+    }
+    fn stamp(&self, center: Vec2<f32>, pixels: &mut HashSet<Vec2<i32>>) {
+        let origin = self.stamp_origin(center);
+        let size = self.stamp_size();
+        for x in 0..size.x {
+            for y in 0..size.y {
+                if let Shape::Bitmap(mask) = &self.shape {
+                    if !mask[vec2(x, y)] {
+                        continue;
+                    }
+                }
+                pixels.insert(origin + vec2(x, y).map(|x| x as i32));
+            }
+        }
+    }
+    pub fn start(&self, position: Vec2<f32>) -> Rasterizer {
+        Rasterizer {
+            brush: self.clone(),
+            last_position: position,
+            pending: Vec::new(),
+        }
+    }
+}
+
+pub struct Rasterizer {
+    brush: Brush,
+    last_position: Vec2<f32>,
+    // Last pixels of a pixel perfect line, held back until we know they are not a corner
+    pending: Vec<Vec2<i32>>,
+}
+
+impl Rasterizer {
+    // Pixels covered since the last position, may repeat pixels returned before
+    pub fn move_to(&mut self, position: Vec2<f32>) -> Vec<Vec2<i32>> {
+        let a = self.last_position;
+        let b = position;
+        self.last_position = position;
+        let pixels = match self.brush.shape {
+            Shape::Round => self.round(a, b),
+            Shape::PixelPerfect => self.pixel_perfect(a, b),
+            _ => self.stamps(a, b),
+        };
+        self.dithered(pixels)
+    }
+    pub fn finish(&mut self) -> Vec<Vec2<i32>> {
+        let pending = std::mem::take(&mut self.pending);
+        self.dithered(pending)
+    }
+    fn dithered(&self, pixels: Vec<Vec2<i32>>) -> Vec<Vec2<i32>> {
+        pixels
+            .into_iter()
+            .filter(|&pixel| self.brush.dither.includes(pixel))
+            .collect()
+    }
+    fn round(&self, a: Vec2<f32>, b: Vec2<f32>) -> Vec<Vec2<i32>> {
+        fn distance(a: Vec2<f32>, b: Vec2<f32>, p: Vec2<f32>) -> f32 {
+            if Vec2::dot(p - a, b - a) <= 0.0 {
+                return (p - a).len();
+            }
+            if Vec2::dot(p - b, a - b) <= 0.0 {
+                return (p - b).len();
+            }
+            Vec2::skew(p - a, (b - a).normalize_or_zero()).abs()
+        }
+
+        let a = a.map(|x| x.round());
+        let b = b.map(|x| x.round());
+        let size = self.brush.size;
+        let aabb = AABB::points_bounding_box([a, b]).extend_uniform(size);
+        let mut pixels = Vec::new();
+        for x in aabb.x_min.floor() as i32..=aabb.x_max.ceil() as i32 {
+            for y in aabb.y_min.floor() as i32..=aabb.y_max.ceil() as i32 {
+                let p = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                if distance(a, b, p) < size {
+                    pixels.push(vec2(x, y));
+                }
+            }
+        }
+        pixels
+    }
+    fn stamps(&self, a: Vec2<f32>, b: Vec2<f32>) -> Vec<Vec2<i32>> {
+        let mut pixels = HashSet::new();
+        // A stamp every half pixel leaves no gaps
+        let steps = ((b - a).len() * 2.0).ceil() as usize;
+        for i in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                i as f32 / steps as f32
+            };
+            self.brush.stamp(a + (b - a) * t, &mut pixels);
+        }
+        pixels.into_iter().collect()
+    }
+    fn pixel_perfect(&mut self, a: Vec2<f32>, b: Vec2<f32>) -> Vec<Vec2<i32>> {
+        let from = a.map(|x| x.floor() as i32);
+        let to = b.map(|x| x.floor() as i32);
+        let delta = to - from;
+        let steps = delta.x.abs().max(delta.y.abs());
+        let mut pixels = Vec::new();
+        for i in 0..=steps {
+            let pixel = if steps == 0 {
+                from
+            } else {
+                from + (delta.map(|x| x as f32) * i as f32 / steps as f32).map(|x| x.round() as i32)
+            };
+            if self.pending.last() == Some(&pixel) {
+                continue;
+            }
+            self.pending.push(pixel);
+            if let [a, b, c] = self.pending[..] {
+                // b is a corner if the line could go from a to c diagonally
+                let corner = (a.x == b.x || a.y == b.y)
+                    && (b.x == c.x || b.y == c.y)
+                    && a.x != c.x
+                    && a.y != c.y;
+                if corner {
+                    self.pending.remove(1);
+                } else {
+                    pixels.push(self.pending.remove(0));
+                }
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brush(shape: Shape, size: f32, dither: Dither) -> Brush {
+        Brush {
+            shape,
+            size,
+            dither,
+        }
+    }
+
+    fn stroke(brush: &Brush, points: &[Vec2<f32>]) -> HashSet<Vec2<i32>> {
+        let mut rasterizer = brush.start(points[0]);
+        let mut pixels: HashSet<Vec2<i32>> = HashSet::new();
+        for &point in points {
+            pixels.extend(rasterizer.move_to(point));
+        }
+        pixels.extend(rasterizer.finish());
+        pixels
+    }
+
+    fn set(pixels: &[(i32, i32)]) -> HashSet<Vec2<i32>> {
+        pixels.iter().map(|&(x, y)| vec2(x, y)).collect()
+    }
+
+    fn brush_pixels(dither: Dither) -> HashSet<Vec2<i32>> {
+        stroke(&brush(Shape::Square, 2.0, dither), &[vec2(0.0, 0.0)])
+    }
+
+    #[test]
+    fn square() {
+        let brush = brush(Shape::Square, 1.0, Dither::None);
+        assert_eq!(
+            stroke(&brush, &[vec2(5.0, 5.0)]),
+            set(&[(4, 4), (5, 4), (4, 5), (5, 5)]),
+        );
+        assert_eq!(
+            stroke(&brush, &[vec2(5.0, 5.0), vec2(7.0, 5.0)]),
+            set(&[
+                (4, 4),
+                (5, 4),
+                (6, 4),
+                (7, 4),
+                (4, 5),
+                (5, 5),
+                (6, 5),
+                (7, 5)
+            ]),
+        );
+    }
+
+    #[test]
+    fn bitmap() {
+        let mask = Matrix::from_fn(vec2(3, 1), |pos| pos.x != 1);
+        let brush = brush(Shape::Bitmap(mask), 1.0, Dither::None);
+        assert_eq!(
+            stroke(&brush, &[vec2(10.0, 10.0)]),
+            set(&[(9, 10), (11, 10)])
+        );
+    }
+
+    #[test]
+    fn dither() {
+        let brush = brush(Shape::Square, 2.0, Dither::Sparse);
+        assert_eq!(
+            stroke(&brush, &[vec2(0.0, 0.0)]),
+            set(&[(-2, -2), (0, -2), (-2, 0), (0, 0)]),
+        );
+        let checker = brush_pixels(Dither::Checker);
+        assert_eq!(checker.len(), 8);
+        assert!(checker
+            .iter()
+            .all(|pixel| (pixel.x + pixel.y).rem_euclid(2) == 0));
+        assert_eq!(brush_pixels(Dither::Dense).len(), 12);
+        assert_eq!(brush_pixels(Dither::None).len(), 16);
+    }
+
+    #[test]
+    fn pixel_perfect_removes_corners() {
+        let brush = brush(Shape::PixelPerfect, 1.0, Dither::None);
+        assert_eq!(
+            stroke(&brush, &[vec2(0.5, 0.5), vec2(1.5, 0.5), vec2(1.5, 1.5)]),
+            set(&[(0, 0), (1, 1)]),
+        );
+    }
+
+    #[test]
+    fn pixel_perfect_keeps_straight_lines() {
+        let brush = brush(Shape::PixelPerfect, 1.0, Dither::None);
+        assert_eq!(
+            stroke(&brush, &[vec2(0.5, 0.5), vec2(3.5, 0.5)]),
+            set(&[(0, 0), (1, 0), (2, 0), (3, 0)]),
+        );
+    }
+
+    #[test]
+    fn sanitize() {
+        let mut huge = brush(Shape::Round, 1e9, Dither::None);
+        huge.sanitize();
+        assert_eq!(huge.size, Brush::MAX_SIZE);
+        let mut nan = brush(Shape::Square, f32::NAN, Dither::None);
+        nan.sanitize();
+        assert_eq!(nan.size, Brush::new().size);
+    }
+}
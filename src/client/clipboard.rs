@@ -0,0 +1,62 @@
+use super::*;
+
+// Images also go to the system clipboard where possible,
+// so they can be pasted into another canvas or another program
+pub struct Clipboard {
+    image: Option<Matrix<Rgba<u8>>>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self { image: None }
+    }
+    pub fn set(&mut self, image: Matrix<Rgba<u8>>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = Self::set_system(&image) {
+            warn!("Failed to copy to the system clipboard: {}", e);
+        }
+        self.image = Some(image);
+    }
+    pub fn get(&mut self) -> Option<Matrix<Rgba<u8>>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        match Self::get_system() {
+            Ok(image) => return Some(image),
+            Err(e) => warn!("Failed to paste from the system clipboard: {}", e),
+        }
+        self.image.clone()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_system(image: &Matrix<Rgba<u8>>) -> Result<(), arboard::Error> {
+        let size = image.size();
+        let mut bytes = Vec::with_capacity(size.x * size.y * 4);
+        for y in (0..size.y).rev() {
+            for x in 0..size.x {
+                let color = image[vec2(x, y)];
+                bytes.extend([color.r, color.g, color.b, color.a]);
+            }
+        }
+        arboard::Clipboard::new()?.set_image(arboard::ImageData {
+            width: size.x,
+            height: size.y,
+            bytes: bytes.into(),
+        })
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn get_system() -> Result<Matrix<Rgba<u8>>, arboard::Error> {
+        let image = arboard::Clipboard::new()?.get_image()?;
+        let mut result =
+            Matrix::filled_with(vec2(image.width, image.height), Rgba::TRANSPARENT_BLACK);
+        for x in 0..image.width {
+            for y in 0..image.height {
+                let index = ((image.height - 1 - y) * image.width + x) * 4;
+                result[vec2(x, y)] = Rgba::new(
+                    image.bytes[index],
+                    image.bytes[index + 1],
+                    image.bytes[index + 2],
+                    image.bytes[index + 3],
+                );
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,157 @@
+use super::*;
+
+pub struct History {
+    geng: Geng,
+    range: Option<Option<(u64, u64)>>,
+    time: u64,
+    texture: texture::Infinite,
+    downloading: bool,
+    dragging: bool,
+}
+
+impl History {
+    pub fn new(geng: &Geng) -> Self {
+        Self {
+            geng: geng.clone(),
+            range: None,
+            time: 0,
+            texture: texture::Infinite::new(geng, true),
+            downloading: false,
+            dragging: false,
+        }
+    }
+    pub fn set_range(&mut self, range: Option<(u64, u64)>) {
+        self.range = Some(range);
+        if let Some((_, end)) = range {
+            self.time = end;
+        }
+        self.reload();
+    }
+    fn reload(&mut self) {
+        self.texture = texture::Infinite::new(&self.geng, true);
+        self.downloading = false;
+    }
+    pub fn upload(&mut self, position: Vec2<i32>, time: u64, data: Matrix<Rgba<u8>>) {
+        if time != self.time {
+            return;
+        }
+        self.downloading = false;
+        self.texture.upload(position, data);
+    }
+    pub fn download_rejected(&mut self) {
+        self.downloading = false;
+    }
+    fn slider(&self) -> AABB<f32> {
+        let size = self.geng.window().size().map(|x| x as f32);
+        AABB {
+            x_min: 20.0,
+            x_max: size.x - 20.0,
+            y_min: 20.0,
+            y_max: 40.0,
+        }
+    }
+    fn set_time_from(&mut self, position: Vec2<f64>) {
+        if let Some(Some((start, end))) = self.range {
+            let slider = self.slider();
+            let t = ((position.x as f32 - slider.x_min) / slider.width()).clamp(0.0, 1.0);
+            self.time = start + ((end - start) as f64 * t as f64) as u64;
+        }
+    }
+    pub fn handle_event(&mut self, event: &geng::Event) {
+        match *event {
+            geng::Event::MouseDown {
+                position,
+                button: geng::MouseButton::Left,
+            } => {
+                if self
+                    .slider()
+                    .extend_uniform(10.0)
+                    .contains(position.map(|x| x as f32))
+                {
+                    self.dragging = true;
+                    self.set_time_from(position);
+                }
+            }
+            geng::Event::MouseMove { position, .. } => {
+                if self.dragging {
+                    self.set_time_from(position);
+                }
+            }
+            geng::Event::MouseUp {
+                button: geng::MouseButton::Left,
+                ..
+            } => {
+                if self.dragging {
+                    self.dragging = false;
+                    self.reload();
+                }
+            }
+            _ => {}
+        }
+    }
+    pub fn draw(
+        &mut self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &geng::Camera2d,
+    ) -> Option<ClientMessage> {
+        let request = self.texture.draw(framebuffer, camera, 1.0);
+        let slider = self.slider();
+        self.geng.draw_2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw_2d::Quad::new(slider, Rgba::new(0.0, 0.0, 0.0, 0.5)),
+        );
+        let text = match self.range {
+            None => "Loading history...".to_owned(),
+            Some(None) => "No history".to_owned(),
+            Some(Some((start, end))) => {
+                let t = if end > start {
+                    (self.time - start) as f32 / (end - start) as f32
+                } else {
+                    1.0
+                };
+                let x = slider.x_min + slider.width() * t;
+                self.geng.draw_2d(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    &draw_2d::Quad::new(
+                        AABB {
+                            x_min: x - 3.0,
+                            x_max: x + 3.0,
+                            y_min: slider.y_min - 5.0,
+                            y_max: slider.y_max + 5.0,
+                        },
+                        Rgba::BLACK,
+                    ),
+                );
+                if self.time == end {
+                    "Latest change".to_owned()
+                } else {
+                    format!(
+                        "{} before the latest change",
+                        format_duration((end - self.time) as f64 / 1000.0),
+                    )
+                }
+            }
+        };
+        self.geng.default_font().draw(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &text,
+            vec2(slider.center().x, slider.y_max + 10.0),
+            geng::TextAlign::CENTER,
+            20.0,
+            Rgba::BLACK,
+        );
+        match (request, self.range) {
+            (Some(area), Some(Some(_))) if !self.downloading && !self.dragging => {
+                self.downloading = true;
+                Some(ClientMessage::DownloadHistory {
+                    area,
+                    time: self.time,
+                })
+            }
+            _ => None,
+        }
+    }
+}
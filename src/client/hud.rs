@@ -0,0 +1,222 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    Syncing(usize),
+    // An update has not been confirmed for a while
+    NotResponding,
+    // Nothing received for a while, pings included
+    Disconnected,
+}
+
+pub struct Status {
+    pub tool: Tool,
+    pub color: Rgba<f32>,
+    pub brush_size: f32,
+    // Screen pixels per canvas pixel
+    pub zoom: f32,
+    pub connection: ConnectionStatus,
+}
+
+struct Button {
+    rect: AABB<f32>,
+    label: String,
+    fill: Rgba<f32>,
+    text_color: Rgba<f32>,
+    active: bool,
+    action: Option<input::Action>,
+}
+
+// Toolbar on the left, every button does the same as the key bound to its action.
+// Drawn with draw_2d rather than geng::ui: geng is a git dependency without a pinned
+// revision (and Cargo.lock is not committed), so there is no fixed geng::ui API to build on
+pub struct Hud {
+    geng: Geng,
+    pub visible: bool,
+}
+
+impl Hud {
+    const MARGIN: f32 = 10.0;
+    const WIDTH: f32 = 90.0;
+    const ROW_HEIGHT: f32 = 24.0;
+    const SPACING: f32 = 4.0;
+    const FONT_SIZE: f32 = 16.0;
+    pub fn new(geng: &Geng) -> Self {
+        Self {
+            geng: geng.clone(),
+            visible: true,
+        }
+    }
+    fn buttons(&self, status: &Status) -> Vec<Button> {
+        use input::Action;
+        let mut buttons = Vec::new();
+        let mut top = self.geng.window().size().y as f32 - 80.0;
+        let mut next_row = |columns: &[f32]| {
+            let mut x = Self::MARGIN;
+            let rects: Vec<AABB<f32>> = columns
+                .iter()
+                .map(|&width| {
+                    let rect = AABB::point(vec2(x, top - Self::ROW_HEIGHT))
+                        .extend_positive(vec2(width, Self::ROW_HEIGHT));
+                    x += width + Self::SPACING;
+                    rect
+                })
+                .collect();
+            top -= Self::ROW_HEIGHT + Self::SPACING;
+            rects
+        };
+        let button = |rect, label: &str, active, action| Button {
+            rect,
+            label: label.to_owned(),
+            fill: Rgba::new(0.9, 0.9, 0.9, 1.0),
+            text_color: Rgba::BLACK,
+            active,
+            action,
+        };
+        for (label, tool, action) in [
+            ("Brush", Tool::Brush, Action::BrushTool),
+            ("Select", Tool::Select, Action::SelectTool),
+            ("Text", Tool::Text, Action::TextTool),
+            ("Stamp", Tool::Stamp, Action::NextStamp),
+            ("Region", Tool::Region, Action::RegionTool),
+            ("Inspect", Tool::Inspect, Action::InspectTool),
+        ] {
+            let rect = next_row(&[Self::WIDTH])[0];
+            buttons.push(button(rect, label, status.tool == tool, Some(action)));
+        }
+
+        let swatch_width = (Self::WIDTH - 2.0 * Self::SPACING) / 3.0;
+        let rects = next_row(&[swatch_width; 3]);
+        for (rect, (label, color, action)) in rects.into_iter().zip([
+            ("", Rgba::WHITE, Action::White),
+            ("", Rgba::BLACK, Action::Black),
+            ("E", Rgba::TRANSPARENT_BLACK, Action::Eraser),
+        ]) {
+            let mut swatch = button(rect, label, status.color == color, Some(action));
+            if color.a != 0.0 {
+                swatch.fill = color;
+            }
+            buttons.push(swatch);
+        }
+
+        let side = Self::ROW_HEIGHT;
+        let middle = Self::WIDTH - 2.0 * (side + Self::SPACING);
+        for (label, shrink, grow) in [
+            (
+                format!("{}", status.brush_size),
+                Action::Shrink,
+                Action::Grow,
+            ),
+            (
+                format!("x{:.1}", status.zoom),
+                Action::ZoomOut,
+                Action::ZoomIn,
+            ),
+        ] {
+            let rects = next_row(&[side, middle, side]);
+            buttons.push(button(rects[0], "-", false, Some(shrink)));
+            buttons.push(Button {
+                fill: Rgba::TRANSPARENT_BLACK,
+                ..button(rects[1], &label, false, None)
+            });
+            buttons.push(button(rects[2], "+", false, Some(grow)));
+        }
+
+        let (label, color) = match status.connection {
+            ConnectionStatus::Connected => ("Connected".to_owned(), Rgba::new(0.0, 0.6, 0.0, 1.0)),
+            ConnectionStatus::Syncing(updates) => (
+                format!("Syncing {}", updates),
+                Rgba::new(0.8, 0.5, 0.0, 1.0),
+            ),
+            ConnectionStatus::NotResponding => {
+                ("No response".to_owned(), Rgba::new(0.8, 0.0, 0.0, 1.0))
+            }
+            ConnectionStatus::Disconnected => {
+                ("Disconnected".to_owned(), Rgba::new(0.8, 0.0, 0.0, 1.0))
+            }
+        };
+        let rect = next_row(&[Self::WIDTH])[0];
+        buttons.push(Button {
+            fill: Rgba::TRANSPARENT_BLACK,
+            text_color: color,
+            ..button(rect, &label, false, None)
+        });
+        buttons
+    }
+    fn area(buttons: &[Button]) -> AABB<f32> {
+        let corners: Vec<Vec2<f32>> = buttons
+            .iter()
+            .flat_map(|button| {
+                [
+                    button.rect.bottom_left(),
+                    vec2(button.rect.x_max, button.rect.y_max),
+                ]
+            })
+            .collect();
+        AABB::points_bounding_box(corners).extend_uniform(Self::SPACING)
+    }
+    // None if the position is not on the toolbar, so the click goes to the canvas
+    pub fn click(&self, status: &Status, position: Vec2<f64>) -> Option<Option<input::Action>> {
+        if !self.visible {
+            return None;
+        }
+        let position = position.map(|x| x as f32);
+        let buttons = self.buttons(status);
+        if !Self::area(&buttons).contains(position) {
+            return None;
+        }
+        Some(
+            buttons
+                .iter()
+                .find(|button| button.rect.contains(position))
+                .and_then(|button| button.action),
+        )
+    }
+    pub fn draw(&self, framebuffer: &mut ugli::Framebuffer, status: &Status) {
+        if !self.visible {
+            return;
+        }
+        let buttons = self.buttons(status);
+        self.geng.draw_2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw_2d::Quad::new(Self::area(&buttons), Rgba::new(1.0, 1.0, 1.0, 0.8)),
+        );
+        for button in &buttons {
+            self.geng.draw_2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw_2d::Quad::new(button.rect, button.fill),
+            );
+            if button.active {
+                draw_frame(
+                    &self.geng,
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    button.rect,
+                    2.0,
+                    Rgba::BLUE,
+                );
+            } else if button.fill.a != 0.0 {
+                draw_frame(
+                    &self.geng,
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    button.rect,
+                    1.0,
+                    Rgba::new(0.5, 0.5, 0.5, 1.0),
+                );
+            }
+            self.geng.default_font().draw(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &button.label,
+                vec2(button.rect.center().x, button.rect.y_min + 5.0),
+                geng::TextAlign::CENTER,
+                Self::FONT_SIZE,
+                button.text_color,
+            );
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use super::*;
+
+const PREFERENCES_KEY: &str = "bindings.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    // Uses the current tool while held
+    Draw,
+    // Drags the camera while held
+    Pan,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    Copy,
+    Cut,
+    Paste,
+    Confirm,
+    Cancel,
+    FlipHorizontal,
+    FlipVertical,
+    RotateCounterclockwise,
+    RotateClockwise,
+    Grow,
+    Shrink,
+    BrushTool,
+    TextTool,
+    SelectTool,
+    RegionTool,
+    InspectTool,
+    History,
+    SaveStamp,
+    NextStamp,
+    NextTemplate,
+    ToggleMismatches,
+    TemplateOpacity,
+    GoTo,
+    NextBookmark,
+    AddBookmark,
+    RemoveBookmark,
+    ToggleGrid,
+    ToggleCoordinates,
+    ToggleChunkBorders,
+    ToggleMinimap,
+    ToggleHud,
+    White,
+    Black,
+    Eraser,
+    BitmapBrush,
+    BrushShape,
+    Dither,
+    SymmetryMode,
+    SymmetryCenter,
+    NextLayer,
+    AddLayer,
+    ToggleLayer,
+    LayerOpacity,
+    RemoveRegion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    Space,
+}
+
+impl Modifier {
+    fn is_held(self, window: &geng::Window) -> bool {
+        let keys: &[geng::Key] = match self {
+            Self::Shift => &[geng::Key::LShift, geng::Key::RShift],
+            Self::Ctrl => &[geng::Key::LCtrl, geng::Key::RCtrl],
+            Self::Alt => &[geng::Key::LAlt, geng::Key::RAlt],
+            Self::Space => &[geng::Key::Space],
+        };
+        keys.iter().any(|&key| window.is_key_pressed(key))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Input {
+    Key(geng::Key),
+    Mouse(geng::MouseButton),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Binding {
+    pub input: Input,
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+}
+
+fn key(key: geng::Key, modifiers: &[Modifier]) -> Binding {
+    Binding {
+        input: Input::Key(key),
+        modifiers: modifiers.to_vec(),
+    }
+}
+
+fn mouse(button: geng::MouseButton, modifiers: &[Modifier]) -> Binding {
+    Binding {
+        input: Input::Mouse(button),
+        modifiers: modifiers.to_vec(),
+    }
+}
+
+fn default_bindings() -> Vec<(Action, Vec<Binding>)> {
+    use geng::Key;
+    use Action::*;
+    use Modifier::{Ctrl, Shift, Space};
+    vec![
+        (Draw, vec![mouse(geng::MouseButton::Left, &[])]),
+        (
+            Pan,
+            vec![
+                mouse(geng::MouseButton::Middle, &[]),
+                mouse(geng::MouseButton::Left, &[Space]),
+            ],
+        ),
+        (PanLeft, vec![key(Key::Left, &[])]),
+        (PanRight, vec![key(Key::Right, &[])]),
+        (PanUp, vec![key(Key::Up, &[])]),
+        (PanDown, vec![key(Key::Down, &[])]),
+        (ZoomIn, vec![key(Key::Z, &[])]),
+        (ZoomOut, vec![key(Key::Z, &[Shift])]),
+        (Copy, vec![key(Key::C, &[Ctrl])]),
+        (Cut, vec![key(Key::X, &[Ctrl])]),
+        (Paste, vec![key(Key::V, &[Ctrl])]),
+        (Confirm, vec![key(Key::Enter, &[])]),
+        (Cancel, vec![key(Key::Escape, &[])]),
+        (FlipHorizontal, vec![key(Key::F, &[])]),
+        (FlipVertical, vec![key(Key::F, &[Shift])]),
+        (RotateCounterclockwise, vec![key(Key::T, &[])]),
+        (RotateClockwise, vec![key(Key::T, &[Shift])]),
+        (Grow, vec![key(Key::PageUp, &[])]),
+        (Shrink, vec![key(Key::PageDown, &[])]),
+        // Only on the toolbar unless bound in the config
+        (BrushTool, vec![]),
+        (TextTool, vec![key(Key::A, &[])]),
+        (SelectTool, vec![key(Key::S, &[])]),
+        (RegionTool, vec![key(Key::R, &[])]),
+        (InspectTool, vec![key(Key::I, &[])]),
+        (History, vec![key(Key::H, &[])]),
+        (SaveStamp, vec![key(Key::S, &[Ctrl])]),
+        (NextStamp, vec![key(Key::P, &[])]),
+        (NextTemplate, vec![key(Key::G, &[])]),
+        (ToggleMismatches, vec![key(Key::G, &[Shift])]),
+        (TemplateOpacity, vec![key(Key::J, &[])]),
+        (GoTo, vec![key(Key::Q, &[])]),
+        (NextBookmark, vec![key(Key::Y, &[])]),
+        (AddBookmark, vec![key(Key::Y, &[Shift])]),
+        (RemoveBookmark, vec![key(Key::Y, &[Ctrl])]),
+        (ToggleGrid, vec![key(Key::Num1, &[])]),
+        (ToggleCoordinates, vec![key(Key::Num2, &[])]),
+        (ToggleChunkBorders, vec![key(Key::Num3, &[])]),
+        (ToggleMinimap, vec![key(Key::Tab, &[])]),
+        (ToggleHud, vec![key(Key::U, &[])]),
+        (White, vec![key(Key::W, &[])]),
+        (Black, vec![key(Key::B, &[])]),
+        (Eraser, vec![key(Key::E, &[])]),
+        (BitmapBrush, vec![key(Key::B, &[Ctrl])]),
+        (BrushShape, vec![key(Key::K, &[])]),
+        (Dither, vec![key(Key::D, &[])]),
+        (SymmetryMode, vec![key(Key::M, &[])]),
+        (SymmetryCenter, vec![key(Key::M, &[Shift])]),
+        (NextLayer, vec![key(Key::L, &[])]),
+        (AddLayer, vec![key(Key::N, &[])]),
+        (ToggleLayer, vec![key(Key::V, &[])]),
+        (LayerOpacity, vec![key(Key::O, &[])]),
+        (RemoveRegion, vec![key(Key::Delete, &[])]),
+    ]
+}
+
+// Turns keys and mouse buttons into actions, bindings are read from preferences
+// (a file on native, local storage on the web) and actions missing there keep the defaults
+pub struct Controls {
+    geng: Geng,
+    bindings: Vec<(Action, Vec<Binding>)>,
+    // Mouse buttons that started an action, so releasing them ends it
+    // even if the modifiers changed in between
+    pressed: Vec<(geng::MouseButton, Action)>,
+}
+
+impl Controls {
+    pub fn new(geng: &Geng) -> Self {
+        let mut bindings = default_bindings();
+        match batbox::preferences::load::<HashMap<Action, Vec<Binding>>>(PREFERENCES_KEY) {
+            Some(mut custom) => {
+                for (action, action_bindings) in &mut bindings {
+                    if let Some(custom) = custom.remove(action) {
+                        *action_bindings = custom;
+                    }
+                }
+            }
+            None if Self::is_saved() => {
+                error!(
+                    "Failed to parse {}, using the default bindings",
+                    PREFERENCES_KEY
+                );
+            }
+            // Saved so there is a file to edit
+            None => batbox::preferences::save(
+                PREFERENCES_KEY,
+                &bindings.iter().cloned().collect::<HashMap<_, _>>(),
+            ),
+        }
+        Self {
+            geng: geng.clone(),
+            bindings,
+            pressed: Vec::new(),
+        }
+    }
+    // A broken file is left alone so the edits in it are not lost,
+    // local storage is not edited by hand so it is just replaced
+    #[cfg(not(target_arch = "wasm32"))]
+    fn is_saved() -> bool {
+        batbox::preferences::base_path()
+            .join(PREFERENCES_KEY)
+            .exists()
+    }
+    #[cfg(target_arch = "wasm32")]
+    fn is_saved() -> bool {
+        false
+    }
+    fn is_held(&self, binding: &Binding) -> bool {
+        let window = self.geng.window();
+        binding
+            .modifiers
+            .iter()
+            .all(|modifier| modifier.is_held(window))
+    }
+    // The binding with the most held modifiers wins, so Shift+G is not also G
+    fn resolve(&self, input: Input) -> Option<Action> {
+        let mut result: Option<(Action, usize)> = None;
+        for (action, bindings) in &self.bindings {
+            for binding in bindings {
+                if binding.input != input || !self.is_held(binding) {
+                    continue;
+                }
+                if result.map_or(true, |(_, modifiers)| binding.modifiers.len() > modifiers) {
+                    result = Some((*action, binding.modifiers.len()));
+                }
+            }
+        }
+        result.map(|(action, _)| action)
+    }
+    pub fn key_down(&self, key: geng::Key) -> Option<Action> {
+        self.resolve(Input::Key(key))
+    }
+    pub fn mouse_down(&mut self, button: geng::MouseButton) -> Option<Action> {
+        let action = self.resolve(Input::Mouse(button))?;
+        self.pressed.retain(|&(pressed, _)| pressed != button);
+        self.pressed.push((button, action));
+        Some(action)
+    }
+    pub fn mouse_up(&mut self, button: geng::MouseButton) -> Option<Action> {
+        let index = self
+            .pressed
+            .iter()
+            .position(|&(pressed, _)| pressed == button)?;
+        Some(self.pressed.remove(index).1)
+    }
+    // For actions that last while a key is held
+    pub fn is_active(&self, action: Action) -> bool {
+        let window = self.geng.window();
+        self.bindings
+            .iter()
+            .filter(|(bound, _)| *bound == action)
+            .flat_map(|(_, bindings)| bindings)
+            .any(|binding| match binding.input {
+                Input::Key(key) => window.is_key_pressed(key) && self.is_held(binding),
+                Input::Mouse(button) => self.pressed.contains(&(button, action)),
+            })
+    }
+}
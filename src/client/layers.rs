@@ -0,0 +1,120 @@
+use super::*;
+
+pub struct Layer {
+    pub name: String,
+    pub texture: texture::Infinite,
+    pub visible: bool,
+    pub opacity: f32,
+}
+
+impl Layer {
+    fn new(geng: &Geng, name: &str) -> Self {
+        let mut texture = texture::Infinite::new(geng, true);
+        // Only the base layer shows unloaded chunks, otherwise they would cover the layers below
+        texture.set_show_unloaded(name == BASE_LAYER);
+        Self {
+            name: name.to_owned(),
+            texture,
+            visible: true,
+            opacity: 1.0,
+        }
+    }
+}
+
+// Layers of the canvas from bottom to top
+pub struct Layers {
+    geng: Geng,
+    layers: Vec<Layer>,
+    current: usize,
+}
+
+impl Layers {
+    pub fn new(geng: &Geng) -> Self {
+        Self {
+            geng: geng.clone(),
+            layers: vec![Layer::new(geng, BASE_LAYER)],
+            current: 0,
+        }
+    }
+    pub fn set_names(&mut self, names: Vec<String>) {
+        let current = self.current().name.clone();
+        let mut old: HashMap<String, Layer> = self
+            .layers
+            .drain(..)
+            .map(|layer| (layer.name.clone(), layer))
+            .collect();
+        for name in names {
+            let layer = old
+                .remove(&name)
+                .unwrap_or_else(|| Layer::new(&self.geng, &name));
+            self.layers.push(layer);
+        }
+        if self.layers.is_empty() {
+            self.layers.push(Layer::new(&self.geng, BASE_LAYER));
+        }
+        self.current = self
+            .layers
+            .iter()
+            .position(|layer| layer.name == current)
+            .unwrap_or(0);
+    }
+    pub fn current(&self) -> &Layer {
+        &self.layers[self.current]
+    }
+    pub fn current_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.current]
+    }
+    pub fn select_next(&mut self) {
+        self.current = (self.current + 1) % self.layers.len();
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.layers.iter().any(|layer| layer.name == name)
+    }
+    fn get_or_create(&mut self, name: &str) -> &mut Layer {
+        let index = match self.layers.iter().position(|layer| layer.name == name) {
+            Some(index) => index,
+            None => {
+                self.layers.push(Layer::new(&self.geng, name));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[index]
+    }
+    // Composited color of all layers, None if the base layer is not loaded there
+    pub fn get(&self, position: Vec2<i32>) -> Option<Rgba<u8>> {
+        let mut color = self.layers[0].texture.get(position)?;
+        for layer in &self.layers[1..] {
+            if let Some(over) = layer.texture.get(position) {
+                color = blend(color, over);
+            }
+        }
+        Some(color)
+    }
+    pub fn update(&mut self, update: Update) -> Update {
+        self.get_or_create(update.layer()).texture.update(update)
+    }
+    pub fn upload(&mut self, layer: &str, position: Vec2<i32>, data: Matrix<Rgba<u8>>) {
+        self.get_or_create(layer).texture.upload(position, data);
+    }
+    // Previews are drawn right above the layer they are on
+    pub fn draw(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &impl geng::AbstractCamera2d,
+        previews: &[(&str, &texture::Infinite)],
+    ) -> Option<(String, AABB<i32>)> {
+        let mut request = None;
+        for layer in &self.layers {
+            if !layer.visible {
+                continue;
+            }
+            if let Some(area) = layer.texture.draw(framebuffer, camera, layer.opacity) {
+                request.get_or_insert((layer.name.clone(), area));
+            }
+            for (_, preview) in previews.iter().filter(|(name, _)| *name == layer.name) {
+                preview.draw(framebuffer, camera, layer.opacity);
+            }
+        }
+        request
+    }
+}
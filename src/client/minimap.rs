@@ -0,0 +1,134 @@
+use super::*;
+
+// Overview of the world around the camera in the top right corner
+pub struct Minimap {
+    geng: Geng,
+    pub visible: bool,
+    overview: Option<(AABB<i32>, ugli::Texture)>,
+    request_cooldown: f64,
+    cursors: HashMap<ClientId, (Option<String>, Vec2<i32>)>,
+}
+
+impl Minimap {
+    const SIZE: f32 = 200.0;
+    const MARGIN: f32 = 20.0;
+    const REQUEST_INTERVAL: f64 = 2.0;
+    pub fn new(geng: &Geng) -> Self {
+        Self {
+            geng: geng.clone(),
+            visible: true,
+            overview: None,
+            request_cooldown: 0.0,
+            cursors: default(),
+        }
+    }
+    pub fn update(&mut self, delta_time: f64, camera: &geng::Camera2d) -> Option<ClientMessage> {
+        self.request_cooldown -= delta_time;
+        if !self.visible || self.request_cooldown > 0.0 {
+            return None;
+        }
+        self.request_cooldown = Self::REQUEST_INTERVAL;
+        Some(ClientMessage::Overview {
+            center: camera.center.map(|x| x as i32),
+        })
+    }
+    pub fn set_overview(&mut self, area: AABB<i32>, data: Matrix<Rgba<u8>>) {
+        self.overview = Some((area, create_texture(&self.geng, &data)));
+    }
+    pub fn set_cursor(
+        &mut self,
+        client_id: ClientId,
+        author: Option<String>,
+        position: Option<Vec2<i32>>,
+    ) {
+        match position {
+            Some(position) => {
+                self.cursors.insert(client_id, (author, position));
+            }
+            None => {
+                self.cursors.remove(&client_id);
+            }
+        }
+    }
+    fn rect(&self) -> AABB<f32> {
+        let size = self.geng.window().size().map(|x| x as f32);
+        AABB::point(size - vec2(Self::MARGIN + Self::SIZE, Self::MARGIN + Self::SIZE))
+            .extend_positive(vec2(Self::SIZE, Self::SIZE))
+    }
+    fn world_to_screen(&self, area: AABB<i32>, position: Vec2<f32>) -> Vec2<f32> {
+        let rect = self.rect();
+        let area = area.map(|x| x as f32);
+        vec2(
+            rect.x_min + (position.x - area.x_min) / area.width() * rect.width(),
+            rect.y_min + (position.y - area.y_min) / area.height() * rect.height(),
+        )
+    }
+    // Where the camera should jump after clicking the minimap
+    pub fn world_position(&self, screen_position: Vec2<f64>) -> Option<Vec2<f32>> {
+        let area = match &self.overview {
+            Some((area, _)) if self.visible => *area,
+            _ => return None,
+        };
+        let rect = self.rect();
+        let position = screen_position.map(|x| x as f32);
+        if !rect.contains(position) {
+            return None;
+        }
+        let area = area.map(|x| x as f32);
+        Some(vec2(
+            area.x_min + (position.x - rect.x_min) / rect.width() * area.width(),
+            area.y_min + (position.y - rect.y_min) / rect.height() * area.height(),
+        ))
+    }
+    pub fn draw(&self, framebuffer: &mut ugli::Framebuffer, view: AABB<f32>) {
+        let (area, texture) = match &self.overview {
+            Some((area, texture)) if self.visible => (*area, texture),
+            _ => return,
+        };
+        let camera = &geng::PixelPerfectCamera;
+        let rect = self.rect();
+        self.geng
+            .draw_2d(framebuffer, camera, &draw_2d::Quad::new(rect, Rgba::WHITE));
+        self.geng.draw_2d(
+            framebuffer,
+            camera,
+            &draw_2d::TexturedQuad::new(rect, texture),
+        );
+        for (author, position) in self.cursors.values() {
+            let position = self.world_to_screen(area, position.map(|x| x as f32));
+            if !rect.contains(position) {
+                continue;
+            }
+            self.geng.draw_2d(
+                framebuffer,
+                camera,
+                &draw_2d::Ellipse::circle(position, 3.0, Rgba::new(1.0, 0.0, 0.0, 1.0)),
+            );
+            if let Some(author) = author {
+                self.geng.default_font().draw(
+                    framebuffer,
+                    camera,
+                    author,
+                    position + vec2(0.0, 5.0),
+                    geng::TextAlign::CENTER,
+                    12.0,
+                    Rgba::BLACK,
+                );
+            }
+        }
+        let viewport = AABB::points_bounding_box([
+            self.world_to_screen(area, view.bottom_left()),
+            self.world_to_screen(area, vec2(view.x_max, view.y_max)),
+        ]);
+        let viewport = AABB {
+            x_min: viewport.x_min.max(rect.x_min),
+            y_min: viewport.y_min.max(rect.y_min),
+            x_max: viewport.x_max.min(rect.x_max),
+            y_max: viewport.y_max.min(rect.y_max),
+        };
+        if viewport.x_min < viewport.x_max && viewport.y_min < viewport.y_max {
+            draw_frame(&self.geng, framebuffer, camera, viewport, 1.0, Rgba::BLUE);
+        }
+        draw_frame(&self.geng, framebuffer, camera, rect, 1.0, Rgba::BLACK);
+    }
+}
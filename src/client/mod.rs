@@ -1,6 +1,22 @@
 use super::*;
 
+mod brush;
+mod clipboard;
+mod history;
+mod hud;
+mod input;
+mod layers;
+mod minimap;
+mod overlays;
+mod prompt;
+mod selection;
+mod settings;
+mod stamps;
+mod symmetry;
+mod template;
+mod text;
 mod texture;
+mod touch;
 
 type Connection = geng::net::client::Connection<ServerMessage, ClientMessage>;
 
@@ -9,104 +25,990 @@ struct ReversibleUpdate {
     backward: Update,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Brush,
+    Region,
+    Inspect,
+    Select,
+    Text,
+    Stamp,
+}
+
 pub struct Client {
     geng: Geng,
     connection: Connection,
-    state: texture::Infinite,
+    canvas: String,
+    settings: settings::Settings,
+    settings_cooldown: f64,
+    unsaved_settings: Option<settings::Settings>,
+    view_order: u64,
+    state: layers::Layers,
     framebuffer_size: Vec2<usize>,
     camera: geng::Camera2d,
+    tool: Tool,
     stroke: Option<Stroke>,
+    rect_start: Option<Vec2<f32>>,
+    selection: Option<selection::Selection>,
+    selection_drag: Option<Vec2<i32>>,
+    clipboard: clipboard::Clipboard,
     color: Rgba<f32>,
-    brush_size: f32,
+    brush: brush::Brush,
+    symmetry: symmetry::Symmetry,
+    text: Option<text::Text>,
+    text_scale: usize,
+    stamps: stamps::Stamps,
+    templates: Vec<TemplateInfo>,
+    template: Option<template::Template>,
+    mismatches_cooldown: f64,
+    minimap: minimap::Minimap,
+    hud: hud::Hud,
+    // Seconds since the client started
+    time: f64,
+    last_message: f64,
+    ping_cooldown: f64,
+    last_cursor: Option<Vec2<i32>>,
+    cursor_cooldown: f64,
+    prompt: Option<prompt::Prompt>,
+    overlays: overlays::Overlays,
+    bookmarks: Vec<Bookmark>,
+    bookmark: Option<String>,
+    controls: input::Controls,
+    gestures: touch::Gestures,
     camera_drag_start: Option<Vec2<f32>>,
     next_update_id: UpdateId,
-    unconfirmed_updates: Vec<(UpdateId, ReversibleUpdate)>,
+    // With the time they were sent at, None while they wait in the outgoing queue
+    unconfirmed_updates: Vec<(UpdateId, Option<f64>, ReversibleUpdate)>,
+    // Updates are sent no faster than the server refills the pixel budget
+    outgoing_updates: VecDeque<(UpdateId, usize, ClientMessage)>,
+    pixel_allowance: f64,
     downloading: bool,
+    download_cooldown: f64,
+    stream_strokes: bool,
+    previews: HashMap<ClientId, Preview>,
+    user: Option<User>,
+    regions: Vec<Region>,
+    pixel_budget: Option<PixelBudget>,
+    max_update_pixels: usize,
+    pixels_per_second: f64,
+    inspected: Option<(Vec2<i32>, Option<Attribution>)>,
+    history: Option<history::History>,
+}
+
+// Stroke of another client
+struct Preview {
+    layer: String,
+    texture: texture::Infinite,
 }
 
 struct Stroke {
+    layer: String,
     pixels: HashSet<Vec2<i32>>,
     texture: texture::Infinite,
-    last_position: Vec2<f32>,
+    rasterizer: brush::Rasterizer,
 }
 
 impl Client {
-    pub fn new(geng: &Geng, connection: Connection) -> Self {
+    const MIN_FOV: f32 = 100.0;
+    const MAX_FOV: f32 = 3000.0;
+    const KEY_ZOOM: f32 = 1.25;
+    // In screens per second
+    const KEY_PAN_SPEED: f32 = 1.0;
+    const SERVER_TIMEOUT: f64 = 5.0;
+    const PING_INTERVAL: f64 = 2.0;
+    // The server refills its budget when messages arrive, so updates delayed on the way
+    // and arriving together must not use up all of it
+    const PIXEL_RATE_MARGIN: f64 = 0.9;
+    const SETTINGS_CHECK_INTERVAL: f64 = 0.5;
+    pub fn new(geng: &Geng, opt: &Opt, mut connection: Connection) -> Self {
+        if let Some(token) = &opt.token {
+            connection.send(ClientMessage::Authenticate {
+                token: token.clone(),
+            });
+        }
+        connection.send(ClientMessage::Join {
+            canvas: opt.canvas.clone(),
+        });
+        if let Some(CooldownSetting(cooldown)) = opt.set_cooldown {
+            connection.send(ClientMessage::SetCooldown(cooldown));
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let template = opt.template.as_ref().and_then(|path| {
+            match template::Template::load(geng, path, opt.template_position) {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    error!("Failed to load template {:?}: {}", path, e);
+                    None
+                }
+            }
+        });
+        #[cfg(target_arch = "wasm32")]
+        let template = None;
+        let settings = settings::Settings::load();
+        // Arguments override where the canvas was left last time
+        let view = settings.views.get(&opt.canvas);
+        let center = view.map_or(vec2(0.0, 0.0), |view| view.center);
+        let fov = view.map_or(Self::MIN_FOV, |view| view.fov);
+        let mut minimap = minimap::Minimap::new(geng);
+        minimap.visible = settings.minimap;
+        let mut hud = hud::Hud::new(geng);
+        hud.visible = settings.hud;
         Self {
             geng: geng.clone(),
             connection,
-            state: texture::Infinite::new(geng, true),
+            canvas: opt.canvas.clone(),
+            settings_cooldown: 0.0,
+            unsaved_settings: None,
+            view_order: settings.next_view_order(),
+            state: layers::Layers::new(geng),
             framebuffer_size: vec2(1, 1),
             camera: geng::Camera2d {
-                center: vec2(0.0, 0.0),
+                center: vec2(opt.x.unwrap_or(center.x), opt.y.unwrap_or(center.y)),
                 rotation: 0.0,
-                fov: 100.0,
+                fov: opt.fov.unwrap_or(fov).clamp(Self::MIN_FOV, Self::MAX_FOV),
             },
+            tool: Tool::Brush,
             stroke: None,
-            brush_size: 2.0,
-            color: Rgba::BLACK,
+            rect_start: None,
+            selection: None,
+            selection_drag: None,
+            clipboard: clipboard::Clipboard::new(),
+            brush: settings.brush.clone(),
+            symmetry: symmetry::Symmetry::new(),
+            text: None,
+            text_scale: settings.text_scale,
+            stamps: stamps::Stamps::new(geng),
+            templates: Vec::new(),
+            template,
+            mismatches_cooldown: 0.0,
+            minimap,
+            hud,
+            time: 0.0,
+            last_message: 0.0,
+            ping_cooldown: 0.0,
+            last_cursor: None,
+            cursor_cooldown: 0.0,
+            prompt: None,
+            overlays: settings.overlays.clone(),
+            bookmarks: Vec::new(),
+            bookmark: None,
+            color: settings.color,
+            controls: input::Controls::new(geng),
+            gestures: touch::Gestures::new(),
             camera_drag_start: None,
             next_update_id: 0,
             unconfirmed_updates: default(),
+            outgoing_updates: default(),
+            pixel_allowance: f64::INFINITY,
             downloading: false,
+            download_cooldown: 0.0,
+            stream_strokes: opt.stream_strokes,
+            previews: default(),
+            user: None,
+            regions: Vec::new(),
+            pixel_budget: None,
+            max_update_pixels: usize::MAX,
+            pixels_per_second: f64::INFINITY,
+            inspected: None,
+            history: None,
+            settings,
         }
     }
+    fn world_position(&self, position: Vec2<f64>) -> Vec2<f32> {
+        self.camera.screen_to_world(
+            self.framebuffer_size.map(|x| x as f32),
+            position.map(|x| x as f32),
+        )
+    }
     fn screen_to_world(&self, position: Vec2<f64>) -> Vec2<f32> {
+        self.world_position(position).map(|x| x.round())
+    }
+    // Visible part of the world
+    fn view(&self) -> AABB<f32> {
+        AABB::points_bounding_box([
+            self.world_position(vec2(0.0, 0.0)),
+            self.world_position(self.framebuffer_size.map(|x| x as f64)),
+        ])
+    }
+    fn current_settings(&self) -> settings::Settings {
+        let mut settings = settings::Settings {
+            color: self.color,
+            brush: self.brush.clone(),
+            text_scale: self.text_scale,
+            minimap: self.minimap.visible,
+            hud: self.hud.visible,
+            overlays: self.overlays.clone(),
+            views: self.settings.views.clone(),
+        };
+        settings.set_view(
+            &self.canvas,
+            settings::View {
+                center: self.camera.center,
+                fov: self.camera.fov,
+                order: self.view_order,
+            },
+        );
+        settings
+    }
+    // Keeps the world position at the screen position in place
+    fn zoom(&mut self, screen_position: Vec2<f64>, factor: f32) {
+        let prev_pos = self.world_position(screen_position);
+        self.camera.fov = (self.camera.fov * factor).clamp(Self::MIN_FOV, Self::MAX_FOV);
+        let current_pos = self.world_position(screen_position);
+        self.camera.center += prev_pos - current_pos;
+    }
+    fn pixel_at(&self, position: Vec2<f64>) -> Vec2<i32> {
         self.camera
             .screen_to_world(
                 self.framebuffer_size.map(|x| x as f32),
                 position.map(|x| x as f32),
             )
-            .map(|x| x.round())
+            .map(|x| x.floor() as i32)
     }
     fn mouse_move(&mut self, position: Vec2<f32>) {
-        fn distance(a: Vec2<f32>, b: Vec2<f32>, p: Vec2<f32>) -> f32 {
-            if Vec2::dot(p - a, b - a) <= 0.0 {
-                return (p - a).len();
+        let mut full = None;
+        if let Some(stroke) = &mut self.stroke {
+            let new_pixels: Vec<Pixel> = self
+                .symmetry
+                .apply(&stroke.rasterizer.move_to(position))
+                .into_iter()
+                .filter(|&position| stroke.pixels.insert(position))
+                .map(|position| Pixel {
+                    position,
+                    color: self.color.convert(),
+                })
+                .collect();
+            if !new_pixels.is_empty() {
+                let update = Update::Draw {
+                    layer: stroke.layer.clone(),
+                    pixels: new_pixels,
+                };
+                if self.stream_strokes {
+                    self.connection.send(ClientMessage::Preview(update.clone()));
+                }
+                stroke.texture.update(update);
             }
-            if Vec2::dot(p - b, a - b) <= 0.0 {
-                return (p - b).len();
+            if stroke.pixels.len() >= self.max_update_pixels {
+                full = Some((stroke.layer.clone(), std::mem::take(&mut stroke.pixels)));
             }
-            Vec2::skew(p - a, (b - a).normalize_or_zero()).abs()
         }
-
-        if let Some(stroke) = &mut self.stroke {
-            let a = position;
-            let b = stroke.last_position;
-            let aabb = AABB::points_bounding_box([a, b]).extend_uniform(self.brush_size);
-            for x in aabb.x_min.floor() as i32..=aabb.x_max.ceil() as i32 {
-                for y in aabb.y_min.floor() as i32..=aabb.y_max.ceil() as i32 {
-                    let p = vec2(x as f32 + 0.5, y as f32 + 0.5);
-                    if distance(a, b, p) < self.brush_size {
-                        stroke.pixels.insert(vec2(x, y));
-                        stroke.texture.update(Update::Draw(vec![Pixel {
-                            position: vec2(x, y),
-                            color: self.color.convert(),
-                        }]));
+        if let Some((layer, pixels)) = full {
+            self.commit_stroke(layer, pixels);
+        }
+    }
+    fn cancel_stroke(&mut self) {
+        if self.stroke.take().is_some() && self.stream_strokes {
+            self.connection.send(ClientMessage::PreviewEnd);
+        }
+    }
+    // Long strokes are sent in parts, since the server rejects updates above its limit
+    fn commit_stroke(&mut self, layer: String, pixels: HashSet<Vec2<i32>>) {
+        let color = self.color.convert();
+        let pixels: Vec<Pixel> = pixels
+            .into_iter()
+            .map(|position| Pixel { position, color })
+            .collect();
+        for part in pixels.chunks(self.max_update_pixels.max(1)) {
+            self.update(Update::Draw {
+                layer: layer.clone(),
+                pixels: part.to_vec(),
+            });
+        }
+    }
+    fn commit_selection(&mut self) {
+        self.selection_drag = None;
+        let update = match &self.selection {
+            Some(selection) => selection.update(),
+            None => return,
+        };
+        if let Some(update) = update {
+            // Kept so it can be made smaller
+            if !self.fits_update_limit(&update) {
+                return;
+            }
+            self.update(update);
+        }
+        self.selection = None;
+    }
+    fn submit_prompt(&mut self, prompt: prompt::Prompt) {
+        match prompt.kind {
+            prompt::Kind::GoTo => match prompt.position() {
+                Some(position) => self.camera.center = position,
+                None => error!("Expected coordinates as x y, got {:?}", prompt.text),
+            },
+            prompt::Kind::Bookmark => {
+                self.connection.send(ClientMessage::AddBookmark(Bookmark {
+                    name: prompt.text,
+                    center: self.camera.center,
+                    fov: self.camera.fov,
+                }));
+            }
+        }
+    }
+    fn next_bookmark(&mut self) {
+        let index = self.bookmark.as_ref().map_or(0, |current| {
+            self.bookmarks
+                .iter()
+                .position(|bookmark| bookmark.name == *current)
+                .map_or(0, |i| i + 1)
+        });
+        let bookmark = match self.bookmarks.get(index).or_else(|| self.bookmarks.first()) {
+            Some(bookmark) => bookmark,
+            None => return,
+        };
+        info!("Bookmark {:?}", bookmark.name);
+        self.camera.center = bookmark.center;
+        self.camera.fov = bookmark.fov.clamp(Self::MIN_FOV, Self::MAX_FOV);
+        self.bookmark = Some(bookmark.name.clone());
+    }
+    fn commit_text(&mut self) {
+        if let Some(update) = self.text.take().and_then(|text| text.into_update()) {
+            self.update(update);
+        }
+    }
+    fn copy_selection(&mut self, cut: bool) {
+        if let Some(mut selection) = self.selection.take() {
+            self.clipboard.set(selection.pixels().clone());
+            if cut {
+                if let Some(update) = selection.source_update() {
+                    if self.fits_update_limit(&update) {
+                        self.update(update);
+                        selection.source = None;
                     }
                 }
             }
-            stroke.last_position = position;
+            self.selection = Some(selection);
         }
     }
-    fn update(&mut self, update: Update) {
+    fn paste(&mut self) {
+        self.commit_selection();
+        if let Some(pixels) = self.clipboard.get() {
+            let position =
+                self.pixel_at(self.geng.window().mouse_pos()) - pixels.size().map(|x| x as i32) / 2;
+            self.selection = Some(selection::Selection::new(
+                &self.geng,
+                self.state.current().name.clone(),
+                None,
+                position,
+                pixels,
+            ));
+            self.tool = Tool::Select;
+        }
+    }
+    fn region_area(a: Vec2<f32>, b: Vec2<f32>) -> AABB<i32> {
+        AABB::points_bounding_box([a, b]).map(|x| x as i32)
+    }
+    // Applies the update until the server confirms it, returns the id to send it with
+    fn apply_unconfirmed(&mut self, update: Update) -> UpdateId {
         let id = self.next_update_id;
         self.next_update_id += 1;
         let backward = self.state.update(update.clone()); // TODO: no clone
         self.unconfirmed_updates.push((
             id,
+            None,
             ReversibleUpdate {
-                forward: update.clone(), // TODO: no clone
+                forward: update,
                 backward,
             },
         ));
-        self.connection.send(ClientMessage::Update { id, update });
+        id
+    }
+    // The server rejects bigger updates as a whole
+    fn fits_update_limit(&self, update: &Update) -> bool {
+        if update.pixel_count() <= self.max_update_pixels {
+            return true;
+        }
+        error!(
+            "Too many pixels at once ({} > {}), try a smaller area",
+            update.pixel_count(),
+            self.max_update_pixels,
+        );
+        false
+    }
+    fn update(&mut self, update: Update) {
+        if !self.fits_update_limit(&update) {
+            return;
+        }
+        let pixels = update.pixel_count();
+        let id = self.apply_unconfirmed(update.clone()); // TODO: no clone
+        self.send_update(id, pixels, ClientMessage::Update { id, update });
+    }
+    fn send_update(&mut self, id: UpdateId, pixels: usize, message: ClientMessage) {
+        self.outgoing_updates.push_back((id, pixels, message));
+        self.flush_updates();
+    }
+    // The server accepts updates while its budget is positive, so does this
+    fn flush_updates(&mut self) {
+        while self.pixel_allowance > 0.0 {
+            let (id, pixels, message) = match self.outgoing_updates.pop_front() {
+                Some(update) => update,
+                None => break,
+            };
+            self.pixel_allowance -= pixels as f64;
+            self.connection.send(message);
+            if let Some((_, sent, _)) = self
+                .unconfirmed_updates
+                .iter_mut()
+                .find(|(unconfirmed_id, _, _)| *unconfirmed_id == id)
+            {
+                *sent = Some(self.time);
+            }
+        }
+    }
+    fn place_stamp(&mut self, cursor: Vec2<i32>) {
+        let position = match self.stamps.position(cursor) {
+            Some(position) => position,
+            None => return,
+        };
+        let (name, pixels) = self.stamps.current().unwrap();
+        let name = name.to_owned();
+        let layer = self.state.current().name.clone();
+        let update = stamp_update(layer.clone(), pixels, position);
+        let pixel_count = update.pixel_count();
+        let id = self.apply_unconfirmed(update);
+        self.send_update(
+            id,
+            pixel_count,
+            ClientMessage::PlaceStamp {
+                id,
+                layer,
+                name,
+                position,
+            },
+        );
+    }
+    fn status(&self) -> hud::Status {
+        let oldest_unconfirmed = self
+            .unconfirmed_updates
+            .first()
+            .and_then(|&(_, sent, _)| sent)
+            .map_or(0.0, |sent| self.time - sent);
+        let connection = if self.time - self.last_message > Self::SERVER_TIMEOUT {
+            hud::ConnectionStatus::Disconnected
+        } else if oldest_unconfirmed > Self::SERVER_TIMEOUT {
+            hud::ConnectionStatus::NotResponding
+        } else if !self.unconfirmed_updates.is_empty() {
+            hud::ConnectionStatus::Syncing(self.unconfirmed_updates.len())
+        } else {
+            hud::ConnectionStatus::Connected
+        };
+        hud::Status {
+            tool: self.tool,
+            color: self.color,
+            brush_size: self.brush.size,
+            zoom: self.framebuffer_size.y as f32 / self.camera.fov,
+            connection,
+        }
+    }
+    // Clicks on the toolbar and minimap don't reach the canvas
+    fn click_ui(&mut self, position: Vec2<f64>) -> bool {
+        if self.history.is_none() {
+            if let Some(action) = self.hud.click(&self.status(), position) {
+                let center = self.framebuffer_size.map(|x| x as f64) / 2.0;
+                match action {
+                    Some(input::Action::ZoomIn) => self.zoom(center, 1.0 / Self::KEY_ZOOM),
+                    Some(input::Action::ZoomOut) => self.zoom(center, Self::KEY_ZOOM),
+                    Some(action) => self.handle_action(action),
+                    None => {}
+                }
+                return true;
+            }
+        }
+        if let Some(center) = self.minimap.world_position(position) {
+            self.camera.center = center;
+            return true;
+        }
+        false
+    }
+    // Starts using the current tool, with the mouse or a finger
+    fn press(&mut self, screen_position: Vec2<f64>) {
+        let position = self.screen_to_world(screen_position);
+        match self.tool {
+            Tool::Brush => {
+                let position = self.world_position(screen_position);
+                self.stroke = Some(Stroke {
+                    layer: self.state.current().name.clone(),
+                    pixels: default(),
+                    texture: texture::Infinite::new_preview(&self.geng),
+                    rasterizer: self.brush.start(position),
+                });
+                self.mouse_move(position);
+            }
+            Tool::Region => {
+                self.rect_start = Some(position);
+            }
+            Tool::Select => {
+                let pixel = self.pixel_at(screen_position);
+                match &self.selection {
+                    Some(selection) if selection.area().contains(pixel) => {
+                        self.selection_drag = Some(pixel - selection.position);
+                    }
+                    _ => {
+                        self.commit_selection();
+                        self.rect_start = Some(position);
+                    }
+                }
+            }
+            Tool::Text => {
+                self.commit_text();
+                self.text = Some(text::Text::new(
+                    &self.geng,
+                    self.state.current().name.clone(),
+                    self.pixel_at(screen_position),
+                    self.text_scale,
+                    self.color.convert(),
+                ));
+            }
+            Tool::Stamp => {
+                let cursor = self.pixel_at(screen_position);
+                self.place_stamp(cursor);
+            }
+            Tool::Inspect => {
+                let position = self.pixel_at(screen_position);
+                self.connection.send(ClientMessage::Attribution {
+                    layer: self.state.current().name.clone(),
+                    position,
+                });
+            }
+        }
+    }
+    fn drag(&mut self, screen_position: Vec2<f64>) {
+        if let Some(offset) = self.selection_drag {
+            let pixel = self.pixel_at(screen_position);
+            if let Some(selection) = &mut self.selection {
+                selection.position = pixel - offset;
+            }
+        }
+        let position = self.world_position(screen_position);
+        self.mouse_move(position);
+    }
+    fn release(&mut self, screen_position: Vec2<f64>) {
+        let position = self.screen_to_world(screen_position);
+        self.selection_drag = None;
+        if let Some(start) = self.rect_start.take() {
+            let area = Self::region_area(start, position);
+            if area.width() > 0 && area.height() > 0 && self.tool == Tool::Select {
+                self.selection = selection::Selection::lift(&self.geng, &self.state, area);
+                if self.selection.is_none() {
+                    error!("Wait for the area to load before selecting it");
+                }
+            } else if area.width() > 0 && area.height() > 0 {
+                // Shift makes the region editable by yourself instead of read-only
+                let editors = match &self.user {
+                    Some(user) if self.geng.window().is_key_pressed(geng::Key::LShift) => {
+                        vec![user.name.clone()]
+                    }
+                    _ => vec![],
+                };
+                self.connection
+                    .send(ClientMessage::AddRegion(Region { area, editors }));
+            }
+        }
+        if let Some(mut stroke) = self.stroke.take() {
+            let finished = stroke.rasterizer.finish();
+            stroke.pixels.extend(self.symmetry.apply(&finished));
+            self.commit_stroke(stroke.layer, stroke.pixels);
+        }
+    }
+    fn handle_action(&mut self, action: input::Action) {
+        use input::Action;
+        match action {
+            Action::Copy => self.copy_selection(false),
+            Action::Cut => self.copy_selection(true),
+            Action::Paste => self.paste(),
+            Action::Confirm => self.commit_selection(),
+            Action::Cancel => {
+                self.selection = None;
+                self.selection_drag = None;
+            }
+            Action::FlipHorizontal => {
+                if let Some(selection) = &mut self.selection {
+                    selection.flip_x();
+                }
+            }
+            Action::FlipVertical => {
+                if let Some(selection) = &mut self.selection {
+                    selection.flip_y();
+                }
+            }
+            Action::RotateCounterclockwise | Action::RotateClockwise => {
+                if let Some(selection) = &mut self.selection {
+                    selection.rotate(action == Action::RotateClockwise);
+                }
+            }
+            Action::Grow if self.selection.is_some() => {
+                let selection = self.selection.as_mut().unwrap();
+                selection.set_scale(selection.scale() + 1);
+            }
+            Action::Shrink if self.selection.is_some() => {
+                let selection = self.selection.as_mut().unwrap();
+                selection.set_scale(selection.scale() - 1);
+            }
+            Action::Grow => self.brush.grow(),
+            Action::Shrink => self.brush.shrink(),
+            Action::TextTool => {
+                self.tool = match self.tool {
+                    Tool::Text => Tool::Brush,
+                    _ => Tool::Text,
+                };
+            }
+            Action::SaveStamp => {
+                if let Some(selection) = &self.selection {
+                    let name = self.stamps.unused_name();
+                    info!("Saving selection as stamp {:?}", name);
+                    self.connection.send(ClientMessage::AddStamp {
+                        name,
+                        image: selection.pixels().clone(),
+                    });
+                }
+            }
+            Action::NextStamp => {
+                self.commit_selection();
+                match self.stamps.select_next() {
+                    Some(name) => {
+                        let name = name.to_owned();
+                        if !self.stamps.is_loaded(&name) {
+                            self.connection
+                                .send(ClientMessage::DownloadStamp { name: name.clone() });
+                        }
+                        info!("Placing stamp {:?}", name);
+                        self.tool = Tool::Stamp;
+                    }
+                    None => self.tool = Tool::Brush,
+                }
+            }
+            Action::ToggleMismatches => {
+                if let Some(template) = &mut self.template {
+                    template.show_mismatches = !template.show_mismatches;
+                    self.mismatches_cooldown = 0.0;
+                }
+            }
+            Action::NextTemplate => {
+                let index = self.template.as_ref().map_or(0, |template| {
+                    self.templates
+                        .iter()
+                        .position(|info| info.name == template.name)
+                        .map_or(0, |i| i + 1)
+                });
+                match self.templates.get(index) {
+                    Some(info) => {
+                        let name = info.name.clone();
+                        self.connection
+                            .send(ClientMessage::DownloadTemplate { name });
+                    }
+                    None => self.template = None,
+                }
+            }
+            Action::TemplateOpacity => {
+                if let Some(template) = &mut self.template {
+                    template.opacity = if template.opacity <= 0.25 {
+                        1.0
+                    } else {
+                        template.opacity - 0.25
+                    };
+                }
+            }
+            Action::GoTo => {
+                self.prompt = Some(prompt::Prompt::new(prompt::Kind::GoTo));
+            }
+            Action::AddBookmark => {
+                self.prompt = Some(prompt::Prompt::new(prompt::Kind::Bookmark));
+            }
+            Action::RemoveBookmark => {
+                if let Some(name) = self.bookmark.take() {
+                    self.connection.send(ClientMessage::RemoveBookmark(name));
+                }
+            }
+            Action::NextBookmark => self.next_bookmark(),
+            Action::ToggleGrid => self.overlays.pixel_grid = !self.overlays.pixel_grid,
+            Action::ToggleCoordinates => self.overlays.coordinates = !self.overlays.coordinates,
+            Action::ToggleChunkBorders => {
+                self.overlays.chunk_borders = !self.overlays.chunk_borders;
+            }
+            Action::BrushTool => {
+                self.commit_selection();
+                self.tool = Tool::Brush;
+            }
+            Action::ToggleHud => self.hud.visible = !self.hud.visible,
+            Action::ToggleMinimap => {
+                self.minimap.visible = !self.minimap.visible;
+            }
+            Action::SelectTool => {
+                self.commit_selection();
+                self.tool = match self.tool {
+                    Tool::Select => Tool::Brush,
+                    _ => Tool::Select,
+                };
+            }
+            Action::White => {
+                self.color = Rgba::WHITE;
+            }
+            Action::Black => {
+                self.color = Rgba::BLACK;
+            }
+            Action::Eraser => {
+                self.color = Rgba::TRANSPARENT_BLACK;
+            }
+            Action::BitmapBrush => {
+                if let Some(selection) = &self.selection {
+                    let pixels = selection.pixels();
+                    self.brush.shape =
+                        brush::Shape::Bitmap(Matrix::from_fn(pixels.size(), |pos| {
+                            pixels[pos].a != 0
+                        }));
+                    self.tool = Tool::Brush;
+                }
+            }
+            Action::NextLayer => {
+                self.state.select_next();
+                info!("Drawing on layer {:?}", self.state.current().name);
+            }
+            Action::AddLayer => {
+                let name = (2..)
+                    .map(|i| format!("layer-{}", i))
+                    .find(|name| !self.state.contains(name))
+                    .unwrap();
+                self.connection.send(ClientMessage::AddLayer { name });
+            }
+            Action::ToggleLayer => {
+                let layer = self.state.current_mut();
+                layer.visible = !layer.visible;
+            }
+            Action::LayerOpacity => {
+                let layer = self.state.current_mut();
+                layer.opacity = if layer.opacity <= 0.25 {
+                    1.0
+                } else {
+                    layer.opacity - 0.25
+                };
+            }
+            Action::BrushShape => {
+                self.brush.next_shape();
+                info!("Brush shape {:?}", self.brush.shape);
+            }
+            Action::SymmetryMode => {
+                self.symmetry.mode = self.symmetry.mode.next();
+                info!("Symmetry {:?}", self.symmetry.mode);
+            }
+            Action::SymmetryCenter => {
+                self.symmetry.center = self
+                    .screen_to_world(self.geng.window().mouse_pos())
+                    .map(|x| x as i32);
+            }
+            Action::Dither => {
+                self.brush.dither = self.brush.dither.next();
+                info!("Dithering {:?}", self.brush.dither);
+            }
+            Action::RegionTool => {
+                self.commit_selection();
+                self.tool = match self.tool {
+                    Tool::Region => Tool::Brush,
+                    _ => Tool::Region,
+                };
+            }
+            Action::History => {
+                self.cancel_stroke();
+                self.rect_start = None;
+                self.commit_selection();
+                self.commit_text();
+                self.history = Some(history::History::new(&self.geng));
+                self.connection.send(ClientMessage::HistoryRange);
+            }
+            Action::InspectTool => {
+                self.commit_selection();
+                self.tool = match self.tool {
+                    Tool::Inspect => Tool::Brush,
+                    _ => Tool::Inspect,
+                };
+                self.inspected = None;
+            }
+            Action::RemoveRegion if self.tool == Tool::Region => {
+                let position = self.pixel_at(self.geng.window().mouse_pos());
+                if let Some(region) = self
+                    .regions
+                    .iter()
+                    .find(|region| region.area.contains(position))
+                {
+                    self.connection
+                        .send(ClientMessage::RemoveRegion(region.area));
+                }
+            }
+            // Mouse and camera actions are handled with their events
+            _ => {}
+        }
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{:.0}s", seconds)
+    } else if seconds < 60.0 * 60.0 {
+        format!("{:.0}m", seconds / 60.0)
+    } else if seconds < 60.0 * 60.0 * 24.0 {
+        format!("{:.0}h", seconds / 60.0 / 60.0)
+    } else {
+        format!("{:.0}d", seconds / 60.0 / 60.0 / 24.0)
+    }
+}
+
+fn create_texture(geng: &Geng, pixels: &Matrix<Rgba<u8>>) -> ugli::Texture {
+    let mut texture =
+        ugli::Texture::new_with(geng.ugli(), pixels.size(), |pos| pixels[pos].convert());
+    texture.set_filter(ugli::Filter::Nearest);
+    texture
+}
+
+fn draw_outline(
+    geng: &Geng,
+    framebuffer: &mut ugli::Framebuffer,
+    camera: &geng::Camera2d,
+    area: AABB<f32>,
+    color: Rgba<f32>,
+) {
+    draw_frame(geng, framebuffer, camera, area, camera.fov / 300.0, color);
+}
+
+fn draw_frame(
+    geng: &Geng,
+    framebuffer: &mut ugli::Framebuffer,
+    camera: &impl geng::AbstractCamera2d,
+    area: AABB<f32>,
+    width: f32,
+    color: Rgba<f32>,
+) {
+    for side in [
+        AABB {
+            y_max: area.y_min + width,
+            ..area
+        },
+        AABB {
+            y_min: area.y_max - width,
+            ..area
+        },
+        AABB {
+            x_max: area.x_min + width,
+            ..area
+        },
+        AABB {
+            x_min: area.x_max - width,
+            ..area
+        },
+    ] {
+        geng.draw_2d(framebuffer, camera, &draw_2d::Quad::new(side, color));
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let settings = self.current_settings();
+        if settings != self.settings {
+            settings.save();
+        }
     }
 }
 
 impl geng::State for Client {
     fn update(&mut self, delta_time: f64) {
+        self.time += delta_time;
+        self.download_cooldown -= delta_time;
+        let max_allowance = self.pixels_per_second * Self::PIXEL_RATE_MARGIN;
+        self.pixel_allowance =
+            (self.pixel_allowance + delta_time * max_allowance).min(max_allowance);
+        self.flush_updates();
+        self.ping_cooldown -= delta_time;
+        if self.ping_cooldown <= 0.0 {
+            self.ping_cooldown = Self::PING_INTERVAL;
+            self.connection.send(ClientMessage::Ping);
+        }
+        // Changed settings are saved once they stay the same for a whole check
+        self.settings_cooldown -= delta_time;
+        if self.settings_cooldown <= 0.0 {
+            self.settings_cooldown = Self::SETTINGS_CHECK_INTERVAL;
+            let settings = self.current_settings();
+            if settings == self.settings {
+                self.unsaved_settings = None;
+            } else if self.unsaved_settings.as_ref() == Some(&settings) {
+                settings.save();
+                self.settings = settings;
+                self.unsaved_settings = None;
+            } else {
+                self.unsaved_settings = Some(settings);
+            }
+        }
+        if self.prompt.is_none() && self.text.is_none() {
+            let direction = [
+                (input::Action::PanLeft, vec2(-1.0, 0.0)),
+                (input::Action::PanRight, vec2(1.0, 0.0)),
+                (input::Action::PanUp, vec2(0.0, 1.0)),
+                (input::Action::PanDown, vec2(0.0, -1.0)),
+            ]
+            .into_iter()
+            .filter(|&(action, _)| self.controls.is_active(action))
+            .fold(vec2(0.0, 0.0), |sum, (_, direction)| sum + direction);
+            self.camera.center +=
+                direction * self.camera.fov * Self::KEY_PAN_SPEED * delta_time as f32;
+        }
+        self.mismatches_cooldown -= delta_time;
+        if let Some(message) = self.minimap.update(delta_time, &self.camera) {
+            self.connection.send(message);
+        }
+        self.cursor_cooldown -= delta_time;
+        let cursor = self.pixel_at(self.geng.window().mouse_pos());
+        // Looking at the past is not where the cursor is on the live canvas
+        if self.history.is_none() && self.cursor_cooldown <= 0.0 && self.last_cursor != Some(cursor)
+        {
+            self.cursor_cooldown = 0.1;
+            self.last_cursor = Some(cursor);
+            self.connection.send(ClientMessage::Cursor(cursor));
+        }
+        if self.mismatches_cooldown <= 0.0 {
+            self.mismatches_cooldown = 1.0;
+            if let Some(template) = &mut self.template {
+                template.update_mismatches(&self.state);
+            }
+        }
+        if let Some(budget) = &mut self.pixel_budget {
+            if budget.refill_in > 0.0 {
+                budget.refill_in -= delta_time;
+                if budget.refill_in <= 0.0 {
+                    budget.refill_in = 0.0;
+                    budget.remaining = budget.total;
+                }
+            }
+        }
         let new_messages: Vec<ServerMessage> = self.connection.new_messages().collect();
+        if !new_messages.is_empty() {
+            self.last_message = self.time;
+        }
+        // Placed stamps are expanded here so they are confirmed like other updates,
+        // the server sends the image before the first placement that uses it
+        let new_messages: Vec<ServerMessage> = new_messages
+            .into_iter()
+            .filter_map(|message| match message {
+                ServerMessage::Stamp { name, image } => {
+                    self.stamps.add_image(name, image);
+                    None
+                }
+                ServerMessage::PlaceStamp {
+                    your_id,
+                    author,
+                    layer,
+                    name,
+                    position,
+                } => match self.stamps.image(&name) {
+                    Some(image) => Some(ServerMessage::Update {
+                        your_id,
+                        author,
+                        update: stamp_update(layer, image, position),
+                    }),
+                    None => {
+                        error!("Stamp {:?} was placed before it was downloaded", name);
+                        None
+                    }
+                },
+                message => Some(message),
+            })
+            .collect();
         if !new_messages.is_empty() {
             let last_confirmed = new_messages
                 .iter()
@@ -116,46 +1018,265 @@ impl geng::State for Client {
                 })
                 .max();
             let mut redo = Vec::new();
-            while let Some((id, update)) = self.unconfirmed_updates.pop() {
+            while let Some((id, sent, update)) = self.unconfirmed_updates.pop() {
                 self.state.update(update.backward.clone()); // TODO: no clone
                 if Some(id) == last_confirmed {
                     break;
                 }
-                redo.push((id, update));
+                redo.push((id, sent, update));
             }
-            while let Some((id, update)) = self.unconfirmed_updates.pop() {
+            while let Some((_, _, update)) = self.unconfirmed_updates.pop() {
                 self.state.update(update.backward);
             }
             for message in new_messages {
                 match message {
-                    ServerMessage::Download { position, data } => {
+                    ServerMessage::UpdateLimits {
+                        max_pixels,
+                        pixels_per_second,
+                    } => {
+                        self.max_update_pixels = max_pixels;
+                        self.pixels_per_second = pixels_per_second;
+                        self.pixel_allowance = pixels_per_second * Self::PIXEL_RATE_MARGIN;
+                    }
+                    ServerMessage::Layers(names) => {
+                        self.state.set_names(names);
+                    }
+                    ServerMessage::Download {
+                        layer,
+                        position,
+                        data,
+                    } => {
                         assert!(self.downloading);
                         self.downloading = false;
-                        self.state.upload(position, data);
+                        self.state.upload(&layer, position, data);
                     }
-                    ServerMessage::Update { your_id, update } => {
+                    ServerMessage::DownloadRejected { reason } => {
+                        warn!("Download rejected: {}", reason);
+                        self.downloading = false;
+                        self.download_cooldown = 1.0;
+                        if let Some(history) = &mut self.history {
+                            history.download_rejected();
+                        }
+                    }
+                    ServerMessage::Authenticated { user } => {
+                        info!("Logged in as {}", user.name);
+                        self.user = Some(user);
+                    }
+                    ServerMessage::Update {
+                        your_id,
+                        author: _,
+                        update,
+                    } => {
                         self.state.update(update);
                     }
+                    ServerMessage::Preview { client_id, update } => {
+                        let preview = self.previews.entry(client_id).or_insert_with(|| Preview {
+                            layer: update.layer().to_owned(),
+                            texture: texture::Infinite::new_preview(&self.geng),
+                        });
+                        preview.layer = update.layer().to_owned();
+                        preview.texture.update(update);
+                    }
+                    ServerMessage::PreviewEnd { client_id } => {
+                        self.previews.remove(&client_id);
+                    }
+                    ServerMessage::Regions(regions) => {
+                        self.regions = regions;
+                    }
+                    ServerMessage::PixelBudget(budget) => {
+                        self.pixel_budget = budget;
+                    }
+                    ServerMessage::Attribution {
+                        position,
+                        attribution,
+                    } => {
+                        self.inspected = Some((position, attribution));
+                    }
+                    ServerMessage::HistoryRange(range) => {
+                        if let Some(history) = &mut self.history {
+                            history.set_range(range);
+                        }
+                    }
+                    ServerMessage::DownloadHistory {
+                        position,
+                        time,
+                        data,
+                    } => {
+                        if let Some(history) = &mut self.history {
+                            history.upload(position, time, data);
+                        }
+                    }
+                    ServerMessage::Stamps(names) => {
+                        self.stamps.set_names(names);
+                    }
+                    // Handled before the updates
+                    ServerMessage::Stamp { .. } | ServerMessage::PlaceStamp { .. } => {}
+                    ServerMessage::Templates(templates) => {
+                        self.templates = templates;
+                    }
+                    ServerMessage::Template { info, image } => {
+                        self.template = Some(template::Template::new(
+                            &self.geng,
+                            info.name,
+                            info.position,
+                            image,
+                        ));
+                        self.mismatches_cooldown = 0.0;
+                    }
+                    ServerMessage::Overview { area, data } => {
+                        self.minimap.set_overview(area, data);
+                    }
+                    ServerMessage::Cursor {
+                        client_id,
+                        author,
+                        position,
+                    } => {
+                        self.minimap.set_cursor(client_id, author, position);
+                    }
+                    ServerMessage::Bookmarks(bookmarks) => {
+                        self.bookmarks = bookmarks;
+                    }
+                    ServerMessage::Error(message) => {
+                        error!("{}", message);
+                    }
+                    ServerMessage::Pong => {}
                 }
             }
-            while let Some((id, update)) = redo.pop() {
+            while let Some((id, sent, update)) = redo.pop() {
                 self.state.update(update.forward.clone()); // TODO: no clone
-                self.unconfirmed_updates.push((id, update));
+                self.unconfirmed_updates.push((id, sent, update));
             }
         }
     }
     fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
         self.framebuffer_size = framebuffer.size();
         ugli::clear(framebuffer, Some(Rgba::WHITE), None, None);
-        if let Some(request) = self.state.draw(framebuffer, &self.camera) {
-            if !self.downloading {
+        let mut previews: Vec<(&str, &texture::Infinite)> = self
+            .previews
+            .values()
+            .map(|preview| (preview.layer.as_str(), &preview.texture))
+            .collect();
+        if let Some(stroke) = &self.stroke {
+            previews.push((&stroke.layer, &stroke.texture));
+        }
+        if let Some(history) = &mut self.history {
+            if let Some(message) = history.draw(framebuffer, &self.camera) {
+                if self.download_cooldown <= 0.0 {
+                    self.connection.send(message);
+                } else {
+                    history.download_rejected();
+                }
+            }
+        } else if let Some((layer, area)) = self.state.draw(framebuffer, &self.camera, &previews) {
+            if !self.downloading && self.download_cooldown <= 0.0 {
                 self.connection
-                    .send(ClientMessage::Download { area: request });
+                    .send(ClientMessage::Download { layer, area });
                 self.downloading = true;
             }
         }
-        if let Some(stroke) = &self.stroke {
-            stroke.texture.draw(framebuffer, &self.camera);
+        if self.history.is_none() {
+            if let Some(template) = &self.template {
+                template.draw(framebuffer, &self.camera);
+            }
+        }
+        self.overlays.draw(
+            &self.geng,
+            framebuffer,
+            &self.camera,
+            self.view(),
+            self.pixel_at(self.geng.window().mouse_pos()),
+        );
+        for region in &self.regions {
+            self.geng.draw_2d(
+                framebuffer,
+                &self.camera,
+                &draw_2d::Quad::new(
+                    region.area.map(|x| x as f32),
+                    if region.is_editable_by(self.user.as_ref()) {
+                        Rgba::new(0.0, 1.0, 0.0, 0.1)
+                    } else {
+                        Rgba::new(1.0, 0.0, 0.0, 0.1)
+                    },
+                ),
+            );
+        }
+        if let Some(start) = self.rect_start {
+            let end = self.screen_to_world(self.geng.window().mouse_pos());
+            self.geng.draw_2d(
+                framebuffer,
+                &self.camera,
+                &draw_2d::Quad::new(
+                    Self::region_area(start, end).map(|x| x as f32),
+                    Rgba::new(0.0, 0.0, 1.0, 0.2),
+                ),
+            );
+        }
+        if self.history.is_none() {
+            if let Some(selection) = &self.selection {
+                selection.draw(framebuffer, &self.camera);
+            }
+            if let Some(text) = &self.text {
+                text.draw(framebuffer, &self.camera);
+            }
+            if self.tool == Tool::Stamp {
+                let cursor = self.pixel_at(self.geng.window().mouse_pos());
+                self.stamps.draw(framebuffer, &self.camera, cursor);
+            }
+            self.symmetry
+                .draw(&self.geng, framebuffer, &self.camera, self.view());
+        }
+
+        if let Some(budget) = &self.pixel_budget {
+            let mut text = format!("{}/{} pixels", budget.remaining, budget.total);
+            if budget.refill_in > 0.0 {
+                text += &format!(", refill in {:.0}s", budget.refill_in.ceil());
+            }
+            self.geng.default_font().draw(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &text,
+                vec2(10.0, framebuffer.size().y as f32 - 30.0),
+                geng::TextAlign::LEFT,
+                20.0,
+                Rgba::BLACK,
+            );
+        }
+
+        if let Some((position, attribution)) = &self.inspected {
+            let position = position.map(|x| x as f32);
+            self.geng.draw_2d(
+                framebuffer,
+                &self.camera,
+                &draw_2d::Quad::new(
+                    AABB::point(position).extend_positive(vec2(1.0, 1.0)),
+                    Rgba::new(1.0, 0.0, 1.0, 0.5),
+                ),
+            );
+            let text = match attribution {
+                Some(attribution) => format!(
+                    "{}, {} ago",
+                    attribution.author,
+                    format_duration(attribution.age)
+                ),
+                None => "nobody".to_owned(),
+            };
+            self.geng.default_font().draw(
+                framebuffer,
+                &self.camera,
+                &text,
+                position + vec2(0.5, 1.5),
+                geng::TextAlign::CENTER,
+                self.camera.fov / 40.0,
+                Rgba::BLACK,
+            );
+        }
+
+        self.minimap.draw(framebuffer, self.view());
+        if self.history.is_none() {
+            self.hud.draw(framebuffer, &self.status());
+        }
+        if let Some(prompt) = &self.prompt {
+            prompt.draw(&self.geng, framebuffer);
         }
 
         // Draw cursor
@@ -163,48 +1284,54 @@ impl geng::State for Client {
             framebuffer.size().map(|x| x as f32),
             self.geng.window().mouse_pos().map(|x| x as f32),
         );
+        if self.brush.shape != brush::Shape::Round {
+            draw_outline(
+                &self.geng,
+                framebuffer,
+                &self.camera,
+                self.brush.outline(mouse_pos),
+                Rgba::BLACK,
+            );
+            return;
+        }
+        let size = self.brush.size;
         let width = 0.1;
         self.geng.draw_2d(
             framebuffer,
             &self.camera,
             &draw_2d::Ellipse::circle_with_cut(
                 mouse_pos,
-                self.brush_size - width * 2.0,
-                self.brush_size + width * 2.0,
+                size - width * 2.0,
+                size + width * 2.0,
                 Rgba::WHITE,
             ),
         );
         self.geng.draw_2d(
             framebuffer,
             &self.camera,
-            &draw_2d::Ellipse::circle_with_cut(
-                mouse_pos,
-                self.brush_size - width,
-                self.brush_size + width,
-                Rgba::BLACK,
-            ),
+            &draw_2d::Ellipse::circle_with_cut(mouse_pos, size - width, size + width, Rgba::BLACK),
         );
     }
     fn handle_event(&mut self, event: geng::Event) {
+        let action = match event {
+            geng::Event::MouseDown { button, .. } => self.controls.mouse_down(button),
+            geng::Event::MouseUp { button, .. } => self.controls.mouse_up(button),
+            geng::Event::KeyDown { key } => self.controls.key_down(key),
+            _ => None,
+        };
+        // Keys go to the prompt or text while one is being typed
+        let typing = self.prompt.is_some() || self.text.is_some();
+        if let geng::Event::MouseDown { position, .. } = event {
+            if action == Some(input::Action::Draw) && self.click_ui(position) {
+                return;
+            }
+        }
         match event {
             // Camera controls
             geng::Event::Wheel { delta } => {
-                let prev_pos = self.camera.screen_to_world(
-                    self.framebuffer_size.map(|x| x as f32),
-                    self.geng.window().cursor_position().map(|x| x as f32),
-                );
-                self.camera.fov =
-                    (self.camera.fov * 1.01f32.powf(-delta as f32)).clamp(100.0, 3000.0);
-                let current_pos = self.camera.screen_to_world(
-                    self.framebuffer_size.map(|x| x as f32),
-                    self.geng.window().cursor_position().map(|x| x as f32),
-                );
-                self.camera.center += prev_pos - current_pos;
+                self.zoom(self.geng.window().mouse_pos(), 1.01f32.powf(-delta as f32));
             }
-            geng::Event::MouseDown {
-                position,
-                button: geng::MouseButton::Middle,
-            } => {
+            geng::Event::MouseDown { position, .. } if action == Some(input::Action::Pan) => {
                 self.camera_drag_start = Some(self.camera.screen_to_world(
                     self.framebuffer_size.map(|x| x as f32),
                     position.map(|x| x as f32),
@@ -219,63 +1346,95 @@ impl geng::State for Client {
                     self.camera.center += start - current_pos;
                 }
             }
-            geng::Event::MouseUp {
-                button: geng::MouseButton::Middle,
-                ..
-            } => self.camera_drag_start = None,
+            geng::Event::MouseUp { .. } if action == Some(input::Action::Pan) => {
+                self.camera_drag_start = None;
+            }
+            geng::Event::KeyDown { .. } if !typing && action == Some(input::Action::ZoomIn) => {
+                self.zoom(self.geng.window().mouse_pos(), 1.0 / Self::KEY_ZOOM);
+            }
+            geng::Event::KeyDown { .. } if !typing && action == Some(input::Action::ZoomOut) => {
+                self.zoom(self.geng.window().mouse_pos(), Self::KEY_ZOOM);
+            }
             _ => {}
         }
 
-        match event {
-            geng::Event::MouseDown {
-                position,
-                button: geng::MouseButton::Left,
-            } => {
-                let position = self.screen_to_world(position);
-                self.stroke = Some(Stroke {
-                    pixels: default(),
-                    texture: texture::Infinite::new(&self.geng, false),
-                    last_position: position,
-                });
-                self.mouse_move(position);
+        let mut touch_draw = Vec::new();
+        for gesture in self.gestures.handle(&event) {
+            match gesture {
+                touch::Gesture::Pan { from, to } => {
+                    self.camera.center += self.world_position(from) - self.world_position(to);
+                }
+                touch::Gesture::Zoom { center, factor } => self.zoom(center, factor as f32),
+                touch::Gesture::Draw(draw) => touch_draw.push(draw),
             }
-            geng::Event::MouseMove { position, delta: _ } => {
-                let position = self.screen_to_world(position);
-                self.mouse_move(position);
+        }
+
+        // Viewing history is read-only
+        if let Some(history) = &mut self.history {
+            history.handle_event(&event);
+            if action == Some(input::Action::History) {
+                self.history = None;
             }
-            geng::Event::MouseUp {
-                position,
-                button: geng::MouseButton::Left,
-            } => {
-                let position = self.screen_to_world(position);
-                if let Some(stroke) = self.stroke.take() {
-                    self.update(Update::Draw(
-                        stroke
-                            .pixels
-                            .into_iter()
-                            .map(|position| Pixel {
-                                position,
-                                color: self.color.convert(),
-                            })
-                            .collect(),
-                    ));
+            return;
+        }
+
+        for draw in touch_draw {
+            match draw {
+                touch::Draw::Start(position) => {
+                    if !self.click_ui(position) {
+                        self.press(position);
+                    }
                 }
+                touch::Draw::Move(position) => self.drag(position),
+                touch::Draw::End(position) => self.release(position),
+                touch::Draw::Cancel => {
+                    self.cancel_stroke();
+                    self.rect_start = None;
+                    self.selection_drag = None;
+                }
+            }
+        }
+
+        match event {
+            geng::Event::MouseDown { position, .. } if action == Some(input::Action::Draw) => {
+                self.press(position);
+            }
+            geng::Event::MouseMove { position, .. } => self.drag(position),
+            geng::Event::MouseUp { position, .. } if action == Some(input::Action::Draw) => {
+                self.release(position);
             }
-            geng::Event::KeyDown { key } => match key {
-                geng::Key::W => {
-                    self.color = Rgba::WHITE;
+            geng::Event::KeyDown { key } if self.prompt.is_some() => match action {
+                Some(input::Action::Confirm) => {
+                    let prompt = self.prompt.take().unwrap();
+                    self.submit_prompt(prompt);
                 }
-                geng::Key::B => {
-                    self.color = Rgba::BLACK;
+                Some(input::Action::Cancel) => self.prompt = None,
+                _ => self.prompt.as_mut().unwrap().handle_key(key),
+            },
+            geng::Event::KeyDown { key } if self.text.is_some() => match action {
+                Some(input::Action::Confirm) => self.commit_text(),
+                Some(input::Action::Cancel) => self.text = None,
+                Some(input::Action::Grow) => {
+                    self.text_scale = (self.text_scale + 1).min(text::Text::MAX_SCALE);
+                    self.text.as_mut().unwrap().set_scale(self.text_scale);
                 }
-                geng::Key::PageUp => {
-                    self.brush_size = (self.brush_size + 0.5).min(10.0);
+                Some(input::Action::Shrink) => {
+                    self.text_scale = (self.text_scale - 1).max(1);
+                    self.text.as_mut().unwrap().set_scale(self.text_scale);
                 }
-                geng::Key::PageDown => {
-                    self.brush_size = (self.brush_size - 0.5).max(0.5);
+                _ if key == geng::Key::Backspace => self.text.as_mut().unwrap().backspace(),
+                _ => {
+                    let shift = self.geng.window().is_key_pressed(geng::Key::LShift);
+                    if let Some(c) = text::key_char(key, shift) {
+                        self.text.as_mut().unwrap().push(c);
+                    }
                 }
-                _ => {}
             },
+            geng::Event::KeyDown { .. } => {
+                if let Some(action) = action {
+                    self.handle_action(action);
+                }
+            }
             _ => {}
         }
     }
@@ -0,0 +1,91 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Overlays {
+    pub pixel_grid: bool,
+    pub coordinates: bool,
+    pub chunk_borders: bool,
+}
+
+impl Overlays {
+    // Smaller pixels would turn the grid into noise
+    const MIN_GRID_PIXEL_SIZE: f32 = 8.0;
+    pub fn new() -> Self {
+        Self {
+            pixel_grid: false,
+            coordinates: true,
+            chunk_borders: false,
+        }
+    }
+    // Only lines inside the view are drawn
+    fn draw_lines(
+        geng: &Geng,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &geng::Camera2d,
+        view: AABB<f32>,
+        step: i32,
+        color: Rgba<f32>,
+    ) {
+        let width = camera.fov / framebuffer.size().y as f32;
+        let start = view.map(|x| div_down(x.floor() as i32, step) * step);
+        for x in (start.x_min..=view.x_max.ceil() as i32).step_by(step as usize) {
+            let x = x as f32;
+            let line = AABB {
+                x_min: x - width / 2.0,
+                x_max: x + width / 2.0,
+                ..view
+            };
+            geng.draw_2d(framebuffer, camera, &draw_2d::Quad::new(line, color));
+        }
+        for y in (start.y_min..=view.y_max.ceil() as i32).step_by(step as usize) {
+            let y = y as f32;
+            let line = AABB {
+                y_min: y - width / 2.0,
+                y_max: y + width / 2.0,
+                ..view
+            };
+            geng.draw_2d(framebuffer, camera, &draw_2d::Quad::new(line, color));
+        }
+    }
+    pub fn draw(
+        &self,
+        geng: &Geng,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &geng::Camera2d,
+        view: AABB<f32>,
+        cursor: Vec2<i32>,
+    ) {
+        let pixel_size = framebuffer.size().y as f32 / camera.fov;
+        if self.pixel_grid && pixel_size >= Self::MIN_GRID_PIXEL_SIZE {
+            Self::draw_lines(
+                geng,
+                framebuffer,
+                camera,
+                view,
+                1,
+                Rgba::new(0.5, 0.5, 0.5, 0.3),
+            );
+        }
+        if self.chunk_borders {
+            Self::draw_lines(
+                geng,
+                framebuffer,
+                camera,
+                view,
+                texture::Infinite::CHUNK_SIZE as i32,
+                Rgba::new(1.0, 0.0, 0.0, 0.5),
+            );
+        }
+        if self.coordinates {
+            geng.default_font().draw(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &format!("{}, {}", cursor.x, cursor.y),
+                vec2(10.0, framebuffer.size().y as f32 - 55.0),
+                geng::TextAlign::LEFT,
+                20.0,
+                Rgba::BLACK,
+            );
+        }
+    }
+}
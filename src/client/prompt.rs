@@ -0,0 +1,75 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    GoTo,
+    Bookmark,
+}
+
+// A line of text typed at the bottom of the screen
+pub struct Prompt {
+    pub kind: Kind,
+    pub text: String,
+}
+
+impl Prompt {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            text: String::new(),
+        }
+    }
+    pub fn handle_key(&mut self, key: geng::Key) {
+        match (self.kind, key) {
+            (_, geng::Key::Backspace) => {
+                self.text.pop();
+            }
+            // There is no minus key, N types it instead
+            (Kind::GoTo, geng::Key::N) => self.text.push('-'),
+            (Kind::GoTo, key) => {
+                if let Some(c) =
+                    text::key_char(key, false).filter(|c| c.is_ascii_digit() || *c == ' ')
+                {
+                    self.text.push(c);
+                }
+            }
+            (Kind::Bookmark, key) => {
+                if let Some(c) = text::key_char(key, false).filter(|c| c.is_ascii_alphanumeric()) {
+                    self.text.push(c.to_ascii_lowercase());
+                }
+            }
+        }
+    }
+    // Coordinates typed as `x y`
+    pub fn position(&self) -> Option<Vec2<f32>> {
+        let mut parts = self.text.split_whitespace();
+        let x = parts.next()?.parse().ok()?;
+        let y = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(vec2(x, y))
+    }
+    pub fn draw(&self, geng: &Geng, framebuffer: &mut ugli::Framebuffer) {
+        let label = match self.kind {
+            Kind::GoTo => "Go to x y",
+            Kind::Bookmark => "Bookmark name",
+        };
+        let size = framebuffer.size().map(|x| x as f32);
+        let rect = AABB::point(vec2(size.x / 2.0 - 200.0, 60.0)).extend_positive(vec2(400.0, 30.0));
+        geng.draw_2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw_2d::Quad::new(rect, Rgba::new(1.0, 1.0, 1.0, 0.9)),
+        );
+        geng.default_font().draw(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &format!("{}: {}_", label, self.text),
+            vec2(rect.x_min + 5.0, rect.y_min + 5.0),
+            geng::TextAlign::LEFT,
+            20.0,
+            Rgba::BLACK,
+        );
+    }
+}
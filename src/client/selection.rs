@@ -0,0 +1,162 @@
+use super::*;
+
+// Pixels lifted from the canvas or pasted, floating above it until committed
+pub struct Selection {
+    geng: Geng,
+    pub layer: String,
+    pub source: Option<AABB<i32>>,
+    pub position: Vec2<i32>,
+    // Flips and rotations are applied to the unscaled pixels so scaling down is lossless
+    unscaled: Matrix<Rgba<u8>>,
+    scale: usize,
+    transformed: bool,
+    pixels: Matrix<Rgba<u8>>,
+    texture: ugli::Texture,
+}
+
+impl Selection {
+    const MAX_SCALE: usize = 8;
+    pub fn new(
+        geng: &Geng,
+        layer: String,
+        source: Option<AABB<i32>>,
+        position: Vec2<i32>,
+        pixels: Matrix<Rgba<u8>>,
+    ) -> Self {
+        Self {
+            geng: geng.clone(),
+            layer,
+            source,
+            position,
+            texture: create_texture(geng, &pixels),
+            unscaled: pixels.clone(),
+            scale: 1,
+            transformed: false,
+            pixels,
+        }
+    }
+    // None unless the whole area is downloaded, otherwise moving it would erase what is there
+    pub fn lift(geng: &Geng, layers: &layers::Layers, area: AABB<i32>) -> Option<Self> {
+        let layer = layers.current();
+        let mut pixels =
+            Matrix::filled_with(area.size().map(|x| x as usize), Rgba::TRANSPARENT_BLACK);
+        for x in 0..area.width() {
+            for y in 0..area.height() {
+                pixels[vec2(x, y).map(|x| x as usize)] =
+                    layer.texture.get_loaded(area.bottom_left() + vec2(x, y))?;
+            }
+        }
+        Some(Self::new(
+            geng,
+            layer.name.clone(),
+            Some(area),
+            area.bottom_left(),
+            pixels,
+        ))
+    }
+    // Keeps the selection centered where it was
+    fn set_unscaled(&mut self, unscaled: Matrix<Rgba<u8>>, scale: usize) {
+        let old_size = self.pixels.size().map(|x| x as i32);
+        self.unscaled = unscaled;
+        self.scale = scale;
+        self.transformed = true;
+        self.pixels = self.unscaled.scale(scale);
+        self.texture = create_texture(&self.geng, &self.pixels);
+        self.position += (old_size - self.pixels.size().map(|x| x as i32)) / 2;
+    }
+    pub fn flip_x(&mut self) {
+        self.set_unscaled(self.unscaled.flip_x(), self.scale);
+    }
+    pub fn flip_y(&mut self) {
+        self.set_unscaled(self.unscaled.flip_y(), self.scale);
+    }
+    pub fn rotate(&mut self, clockwise: bool) {
+        let mut unscaled = self.unscaled.rotate();
+        if clockwise {
+            unscaled = unscaled.rotate().rotate();
+        }
+        self.set_unscaled(unscaled, self.scale);
+    }
+    pub fn set_scale(&mut self, scale: usize) {
+        let scale = scale.clamp(1, Self::MAX_SCALE);
+        if scale != self.scale {
+            self.set_unscaled(self.unscaled.clone(), scale);
+        }
+    }
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+    pub fn pixels(&self) -> &Matrix<Rgba<u8>> {
+        &self.pixels
+    }
+    pub fn area(&self) -> AABB<i32> {
+        AABB::point(self.position).extend_positive(self.pixels.size().map(|x| x as i32))
+    }
+    pub fn is_changed(&self) -> bool {
+        self.transformed
+            || self
+                .source
+                .map_or(true, |source| source.bottom_left() != self.position)
+    }
+    // Pixels that clear the source area, used when cutting
+    pub fn source_update(&self) -> Option<Update> {
+        let source = self.source?;
+        let mut pixels = Vec::new();
+        for x in source.x_min..source.x_max {
+            for y in source.y_min..source.y_max {
+                pixels.push(Pixel {
+                    position: vec2(x, y),
+                    color: Rgba::TRANSPARENT_BLACK,
+                });
+            }
+        }
+        Some(Update::Draw {
+            layer: self.layer.clone(),
+            pixels,
+        })
+    }
+    pub fn update(&self) -> Option<Update> {
+        if !self.is_changed() {
+            return None;
+        }
+        let mut result = HashMap::new();
+        if let Some(Update::Draw { pixels, .. }) = self.source_update() {
+            for pixel in pixels {
+                result.insert(pixel.position, pixel.color);
+            }
+        }
+        for x in 0..self.pixels.size().x {
+            for y in 0..self.pixels.size().y {
+                let color = self.pixels[vec2(x, y)];
+                if color.a != 0 {
+                    result.insert(self.position + vec2(x, y).map(|x| x as i32), color);
+                }
+            }
+        }
+        Some(Update::Draw {
+            layer: self.layer.clone(),
+            pixels: result
+                .into_iter()
+                .map(|(position, color)| Pixel { position, color })
+                .collect(),
+        })
+    }
+    pub fn draw(&self, framebuffer: &mut ugli::Framebuffer, camera: &geng::Camera2d) {
+        let area = self.area().map(|x| x as f32);
+        if let Some(source) = self.source {
+            if self.is_changed() {
+                self.geng.draw_2d(
+                    framebuffer,
+                    camera,
+                    &draw_2d::Quad::new(source.map(|x| x as f32), Rgba::new(1.0, 1.0, 1.0, 0.7)),
+                );
+            }
+        }
+        self.geng.draw_2d(
+            framebuffer,
+            camera,
+            &draw_2d::TexturedQuad::new(area, &self.texture),
+        );
+        draw_outline(&self.geng, framebuffer, camera, area, Rgba::BLUE);
+    }
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+const PREFERENCES_KEY: &str = "settings.json";
+const MAX_VIEWS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub center: Vec2<f32>,
+    pub fov: f32,
+    // Higher for canvases visited more recently
+    #[serde(default)]
+    pub order: u64,
+}
+
+// Kept in preferences (a file on native, local storage on the web),
+// fields missing from older saves get their defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub color: Rgba<f32>,
+    pub brush: brush::Brush,
+    pub text_scale: usize,
+    pub minimap: bool,
+    pub hud: bool,
+    pub overlays: overlays::Overlays,
+    // Last camera of every canvas
+    pub views: HashMap<String, View>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            color: Rgba::BLACK,
+            brush: brush::Brush::new(),
+            text_scale: 1,
+            minimap: true,
+            hud: true,
+            overlays: overlays::Overlays::new(),
+            views: default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut settings: Self = batbox::preferences::load(PREFERENCES_KEY).unwrap_or_default();
+        settings.text_scale = settings.text_scale.clamp(1, text::Text::MAX_SCALE);
+        settings.brush.sanitize();
+        settings
+    }
+    pub fn save(&self) {
+        batbox::preferences::save(PREFERENCES_KEY, self);
+    }
+    pub fn next_view_order(&self) -> u64 {
+        self.views
+            .values()
+            .map(|view| view.order + 1)
+            .max()
+            .unwrap_or(0)
+    }
+    // Forgets the canvases visited longest ago
+    pub fn set_view(&mut self, canvas: &str, view: View) {
+        self.views.insert(canvas.to_owned(), view);
+        while self.views.len() > MAX_VIEWS {
+            let oldest = self
+                .views
+                .iter()
+                .min_by_key(|(_, view)| view.order)
+                .map(|(canvas, _)| canvas.clone())
+                .unwrap();
+            self.views.remove(&oldest);
+        }
+    }
+}
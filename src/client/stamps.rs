@@ -0,0 +1,90 @@
+use super::*;
+
+struct Stamp {
+    pixels: Matrix<Rgba<u8>>,
+    texture: ugli::Texture,
+}
+
+// Stamps of the canvas, images are downloaded when a stamp is first selected
+pub struct Stamps {
+    geng: Geng,
+    names: Vec<String>,
+    images: HashMap<String, Stamp>,
+    current: Option<String>,
+}
+
+impl Stamps {
+    pub fn new(geng: &Geng) -> Self {
+        Self {
+            geng: geng.clone(),
+            names: Vec::new(),
+            images: default(),
+            current: None,
+        }
+    }
+    pub fn set_names(&mut self, names: Vec<String>) {
+        self.names = names;
+        self.current = self.current.take().filter(|name| self.names.contains(name));
+        // A removed stamp may come back with the same name and another image
+        let names = &self.names;
+        self.images.retain(|name, _| names.contains(name));
+    }
+    pub fn add_image(&mut self, name: String, pixels: Matrix<Rgba<u8>>) {
+        let texture = create_texture(&self.geng, &pixels);
+        self.images.insert(name, Stamp { pixels, texture });
+    }
+    // Returns None after the last stamp
+    pub fn select_next(&mut self) -> Option<&str> {
+        let index = match &self.current {
+            Some(current) => self
+                .names
+                .iter()
+                .position(|name| name == current)
+                .map_or(0, |i| i + 1),
+            None => 0,
+        };
+        self.current = self.names.get(index).cloned();
+        self.current.as_deref()
+    }
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.images.contains_key(name)
+    }
+    pub fn image(&self, name: &str) -> Option<&Matrix<Rgba<u8>>> {
+        Some(&self.images.get(name)?.pixels)
+    }
+    pub fn current(&self) -> Option<(&str, &Matrix<Rgba<u8>>)> {
+        let name = self.current.as_ref()?;
+        Some((name, &self.images.get(name)?.pixels))
+    }
+    pub fn unused_name(&self) -> String {
+        (1..)
+            .map(|i| format!("stamp-{}", i))
+            .find(|name| !self.names.contains(name))
+            .unwrap()
+    }
+    // Stamps are placed centered at the cursor
+    pub fn position(&self, cursor: Vec2<i32>) -> Option<Vec2<i32>> {
+        let (_, pixels) = self.current()?;
+        Some(cursor - pixels.size().map(|x| x as i32) / 2)
+    }
+    pub fn draw(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &geng::Camera2d,
+        cursor: Vec2<i32>,
+    ) {
+        let (name, position) = match (&self.current, self.position(cursor)) {
+            (Some(name), Some(position)) => (name, position),
+            _ => return,
+        };
+        let stamp = &self.images[name];
+        let area = AABB::point(position)
+            .extend_positive(stamp.pixels.size().map(|x| x as i32))
+            .map(|x| x as f32);
+        self.geng.draw_2d(
+            framebuffer,
+            camera,
+            &draw_2d::TexturedQuad::colored(area, &stamp.texture, Rgba::new(1.0, 1.0, 1.0, 0.5)),
+        );
+    }
+}
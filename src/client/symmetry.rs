@@ -0,0 +1,150 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    None,
+    Horizontal,
+    Vertical,
+    Both,
+    Radial(usize),
+    Tile,
+}
+
+impl Mode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Horizontal,
+            Self::Horizontal => Self::Vertical,
+            Self::Vertical => Self::Both,
+            Self::Both => Self::Radial(3),
+            Self::Radial(3) => Self::Radial(4),
+            Self::Radial(4) => Self::Radial(6),
+            Self::Radial(6) => Self::Radial(8),
+            Self::Radial(_) => Self::Tile,
+            Self::Tile => Self::None,
+        }
+    }
+}
+
+// Repeats every stroke pixel around the center, which is a pixel corner,
+// tiles start at the center
+pub struct Symmetry {
+    pub mode: Mode,
+    pub center: Vec2<i32>,
+}
+
+impl Symmetry {
+    const TILE_SIZE: i32 = 32;
+    // Tiles repeated in every direction from the tile that is drawn on
+    const TILE_REPEATS: i32 = 2;
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::None,
+            center: vec2(0, 0),
+        }
+    }
+    pub fn apply(&self, pixels: &[Vec2<i32>]) -> Vec<Vec2<i32>> {
+        match self.mode {
+            Mode::Radial(n) => self.radial(pixels, n),
+            _ => pixels
+                .iter()
+                .flat_map(|&pixel| self.copies(pixel))
+                .collect(),
+        }
+    }
+    fn copies(&self, pixel: Vec2<i32>) -> Vec<Vec2<i32>> {
+        let c = self.center;
+        let mirror_x = |p: Vec2<i32>| vec2(2 * c.x - 1 - p.x, p.y);
+        let mirror_y = |p: Vec2<i32>| vec2(p.x, 2 * c.y - 1 - p.y);
+        match self.mode {
+            Mode::None | Mode::Radial(_) => vec![pixel],
+            Mode::Horizontal => vec![pixel, mirror_x(pixel)],
+            Mode::Vertical => vec![pixel, mirror_y(pixel)],
+            Mode::Both => vec![
+                pixel,
+                mirror_x(pixel),
+                mirror_y(pixel),
+                mirror_x(mirror_y(pixel)),
+            ],
+            Mode::Tile => {
+                let mut result = Vec::new();
+                for x in -Self::TILE_REPEATS..=Self::TILE_REPEATS {
+                    for y in -Self::TILE_REPEATS..=Self::TILE_REPEATS {
+                        result.push(pixel + vec2(x, y) * Self::TILE_SIZE);
+                    }
+                }
+                result
+            }
+        }
+    }
+    // Rotating single pixels leaves holes in diagonal copies, so every pixel around
+    // a rotated pixel is taken if its center rotates back onto one of the stroke pixels
+    fn radial(&self, pixels: &[Vec2<i32>], n: usize) -> Vec<Vec2<i32>> {
+        let center = self.center.map(|x| x as f32);
+        let rotate = |p: Vec2<f32>, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            let offset = p - center;
+            center
+                + vec2(
+                    offset.x * cos - offset.y * sin,
+                    offset.x * sin + offset.y * cos,
+                )
+        };
+        let source: HashSet<Vec2<i32>> = pixels.iter().copied().collect();
+        let mut result: HashSet<Vec2<i32>> = source.clone();
+        for i in 1..n {
+            let angle = 2.0 * std::f32::consts::PI * i as f32 / n as f32;
+            for &pixel in pixels {
+                let corners = [vec2(0, 0), vec2(1, 0), vec2(0, 1), vec2(1, 1)]
+                    .map(|corner| rotate((pixel + corner).map(|x| x as f32), angle));
+                let area = AABB::points_bounding_box(corners);
+                for x in area.x_min.floor() as i32..area.x_max.ceil() as i32 {
+                    for y in area.y_min.floor() as i32..area.y_max.ceil() as i32 {
+                        let back = rotate(vec2(x as f32 + 0.5, y as f32 + 0.5), -angle)
+                            .map(|x| x.floor() as i32);
+                        if source.contains(&back) {
+                            result.insert(vec2(x, y));
+                        }
+                    }
+                }
+            }
+        }
+        result.into_iter().collect()
+    }
+    pub fn draw(
+        &self,
+        geng: &Geng,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &geng::Camera2d,
+        view: AABB<f32>,
+    ) {
+        let color = Rgba::new(1.0, 0.0, 1.0, 0.5);
+        let width = camera.fov / 300.0;
+        let center = self.center.map(|x| x as f32);
+        let vertical = AABB {
+            x_min: center.x - width / 2.0,
+            x_max: center.x + width / 2.0,
+            ..view
+        };
+        let horizontal = AABB {
+            y_min: center.y - width / 2.0,
+            y_max: center.y + width / 2.0,
+            ..view
+        };
+        let lines = match self.mode {
+            Mode::None => return,
+            Mode::Horizontal => vec![vertical],
+            Mode::Vertical => vec![horizontal],
+            Mode::Both | Mode::Radial(_) => vec![vertical, horizontal],
+            Mode::Tile => {
+                let tile = AABB::point(center)
+                    .extend_positive(vec2(Self::TILE_SIZE, Self::TILE_SIZE).map(|x| x as f32));
+                draw_outline(geng, framebuffer, camera, tile, color);
+                return;
+            }
+        };
+        for line in lines {
+            geng.draw_2d(framebuffer, camera, &draw_2d::Quad::new(line, color));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use super::*;
+
+// A reference image only the local user sees
+pub struct Template {
+    geng: Geng,
+    pub name: String,
+    position: Vec2<i32>,
+    pixels: Matrix<Rgba<u8>>,
+    texture: ugli::Texture,
+    pub opacity: f32,
+    pub show_mismatches: bool,
+    mismatches: Option<ugli::Texture>,
+}
+
+impl Template {
+    pub fn new(geng: &Geng, name: String, position: Vec2<i32>, pixels: Matrix<Rgba<u8>>) -> Self {
+        Self {
+            geng: geng.clone(),
+            name,
+            position,
+            texture: create_texture(geng, &pixels),
+            pixels,
+            opacity: 0.5,
+            show_mismatches: false,
+            mismatches: None,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(
+        geng: &Geng,
+        path: &std::path::Path,
+        position: Vec2<i32>,
+    ) -> image::ImageResult<Self> {
+        let pixels = matrix_from_image(&image::open(path)?.to_rgba8());
+        Ok(Self::new(
+            geng,
+            path.display().to_string(),
+            position,
+            pixels,
+        ))
+    }
+    fn area(&self) -> AABB<i32> {
+        AABB::point(self.position).extend_positive(self.pixels.size().map(|x| x as i32))
+    }
+    // Pixels of the template that differ from the canvas, not loaded pixels are not compared
+    pub fn update_mismatches(&mut self, layers: &layers::Layers) {
+        if !self.show_mismatches {
+            self.mismatches = None;
+            return;
+        }
+        let on_white = |color: Rgba<u8>| blend(Rgba::WHITE, color);
+        let mismatches = Matrix::from_fn(self.pixels.size(), |pos| {
+            let expected = self.pixels[pos];
+            let actual = layers.get(self.position + pos.map(|x| x as i32));
+            match actual {
+                Some(actual) if expected.a != 0 && on_white(actual) != on_white(expected) => {
+                    Rgba::new(255, 0, 0, 255)
+                }
+                _ => Rgba::TRANSPARENT_BLACK,
+            }
+        });
+        self.mismatches = Some(create_texture(&self.geng, &mismatches));
+    }
+    pub fn draw(&self, framebuffer: &mut ugli::Framebuffer, camera: &geng::Camera2d) {
+        let area = self.area().map(|x| x as f32);
+        self.geng.draw_2d(
+            framebuffer,
+            camera,
+            &draw_2d::TexturedQuad::colored(
+                area,
+                &self.texture,
+                Rgba::new(1.0, 1.0, 1.0, self.opacity),
+            ),
+        );
+        if let Some(mismatches) = &self.mismatches {
+            self.geng.draw_2d(
+                framebuffer,
+                camera,
+                &draw_2d::TexturedQuad::colored(area, mismatches, Rgba::new(1.0, 1.0, 1.0, 0.7)),
+            );
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use super::*;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// Rows from top to bottom, the highest of 5 bits is the leftmost pixel
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT]> {
+    Some(match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        _ => return None,
+    })
+}
+
+pub fn key_char(key: geng::Key, shift: bool) -> Option<char> {
+    use geng::Key::*;
+    Some(match key {
+        A => 'A',
+        B => 'B',
+        C => 'C',
+        D => 'D',
+        E => 'E',
+        F => 'F',
+        G => 'G',
+        H => 'H',
+        I => 'I',
+        J => 'J',
+        K => 'K',
+        L => 'L',
+        M => 'M',
+        N => 'N',
+        O => 'O',
+        P => 'P',
+        Q => 'Q',
+        R => 'R',
+        S => 'S',
+        T => 'T',
+        U => 'U',
+        V => 'V',
+        W => 'W',
+        X => 'X',
+        Y => 'Y',
+        Z => 'Z',
+        Num1 if shift => '!',
+        Num0 => '0',
+        Num1 => '1',
+        Num2 => '2',
+        Num3 => '3',
+        Num4 => '4',
+        Num5 => '5',
+        Num6 => '6',
+        Num7 => '7',
+        Num8 => '8',
+        Num9 => '9',
+        Space => ' ',
+        _ => return None,
+    })
+}
+
+// Text typed at a position, shown like a stroke until committed
+pub struct Text {
+    geng: Geng,
+    layer: String,
+    position: Vec2<i32>,
+    text: String,
+    scale: usize,
+    color: Rgba<u8>,
+    texture: texture::Infinite,
+}
+
+impl Text {
+    pub const MAX_SCALE: usize = 8;
+    pub fn new(
+        geng: &Geng,
+        layer: String,
+        position: Vec2<i32>,
+        scale: usize,
+        color: Rgba<u8>,
+    ) -> Self {
+        Self {
+            geng: geng.clone(),
+            layer,
+            position,
+            text: String::new(),
+            scale,
+            color,
+            texture: texture::Infinite::new(geng, false),
+        }
+    }
+    pub fn push(&mut self, c: char) {
+        if glyph(c).is_some() {
+            self.text.push(c);
+            self.update_texture();
+        }
+    }
+    pub fn backspace(&mut self) {
+        self.text.pop();
+        self.update_texture();
+    }
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale;
+        self.update_texture();
+    }
+    fn advance(&self) -> i32 {
+        ((GLYPH_WIDTH + 1) * self.scale) as i32
+    }
+    fn pixels(&self) -> Vec<Pixel> {
+        let mut pixels = Vec::new();
+        for (index, c) in self.text.chars().enumerate() {
+            let rows = glyph(c).unwrap();
+            let origin = self.position + vec2(index as i32 * self.advance(), 0);
+            for (row, &bits) in rows.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if (bits >> (GLYPH_WIDTH - 1 - column)) & 1 == 0 {
+                        continue;
+                    }
+                    let cell = origin
+                        + vec2(column, GLYPH_HEIGHT - 1 - row).map(|x| (x * self.scale) as i32);
+                    for x in 0..self.scale as i32 {
+                        for y in 0..self.scale as i32 {
+                            pixels.push(Pixel {
+                                position: cell + vec2(x, y),
+                                color: self.color,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        pixels
+    }
+    fn update_texture(&mut self) {
+        self.texture = texture::Infinite::new(&self.geng, false);
+        self.texture.update(Update::Draw {
+            layer: self.layer.clone(),
+            pixels: self.pixels(),
+        });
+    }
+    pub fn into_update(self) -> Option<Update> {
+        let pixels = self.pixels();
+        if pixels.is_empty() {
+            return None;
+        }
+        Some(Update::Draw {
+            layer: self.layer,
+            pixels,
+        })
+    }
+    pub fn draw(&self, framebuffer: &mut ugli::Framebuffer, camera: &geng::Camera2d) {
+        self.texture.draw(framebuffer, camera, 1.0);
+        let cursor = self.position + vec2(self.text.chars().count() as i32 * self.advance(), 0);
+        let height = (GLYPH_HEIGHT * self.scale) as f32;
+        self.geng.draw_2d(
+            framebuffer,
+            camera,
+            &draw_2d::Quad::new(
+                AABB::point(cursor.map(|x| x as f32))
+                    .extend_positive(vec2(camera.fov / 300.0, height)),
+                Rgba::BLUE,
+            ),
+        );
+    }
+}
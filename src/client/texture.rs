@@ -1,8 +1,18 @@
 use super::*;
 
+// Erased pixels of previews are drawn with this, since transparent would show nothing
+const ERASED_COLOR: Rgba<u8> = Rgba {
+    r: 128,
+    g: 128,
+    b: 128,
+    a: 128,
+};
+
 pub struct Infinite {
     geng: Geng,
     ignore_unloaded_updates: bool,
+    show_unloaded: bool,
+    show_erased: bool,
     pixels: HashMap<Vec2<i32>, Rgba<u8>>,
     chunks: HashMap<Vec2<i32>, Chunk>,
 }
@@ -12,15 +22,38 @@ struct Chunk {
 }
 
 impl Infinite {
-    const CHUNK_SIZE: usize = 256;
+    pub const CHUNK_SIZE: usize = 256;
     pub fn new(geng: &Geng, ignore_unloaded_updates: bool) -> Self {
         Self {
             geng: geng.clone(),
             ignore_unloaded_updates,
+            show_unloaded: ignore_unloaded_updates,
+            show_erased: false,
             pixels: default(),
             chunks: HashMap::new(),
         }
     }
+    // For strokes that are not applied yet
+    pub fn new_preview(geng: &Geng) -> Self {
+        Self {
+            show_erased: true,
+            ..Self::new(geng, false)
+        }
+    }
+    pub fn get(&self, position: Vec2<i32>) -> Option<Rgba<u8>> {
+        self.pixels.get(&position).copied()
+    }
+    // None for pixels of chunks that were not downloaded, even if they were drawn on
+    pub fn get_loaded(&self, position: Vec2<i32>) -> Option<Rgba<u8>> {
+        let chunk_pos = position.map(|x| div_down(x, Self::CHUNK_SIZE as _));
+        if !self.chunks.contains_key(&chunk_pos) {
+            return None;
+        }
+        self.get(position)
+    }
+    pub fn set_show_unloaded(&mut self, show_unloaded: bool) {
+        self.show_unloaded = show_unloaded;
+    }
     pub fn upload(&mut self, position: Vec2<i32>, data: Matrix<Rgba<u8>>) {
         assert_eq!(position.x % Self::CHUNK_SIZE as i32, 0);
         assert_eq!(position.y % Self::CHUNK_SIZE as i32, 0);
@@ -48,7 +81,7 @@ impl Infinite {
     }
     pub fn update(&mut self, update: Update) -> Update {
         match update {
-            Update::Draw(pixels) => {
+            Update::Draw { layer, pixels } => {
                 let mut reverse = Vec::with_capacity(pixels.len());
                 for pixel in pixels {
                     reverse.push(Pixel {
@@ -81,11 +114,19 @@ impl Infinite {
                     };
                     let pixel_position =
                         (pixel.position - chunk_pos * Self::CHUNK_SIZE as i32).map(|x| x as usize);
+                    let color = if self.show_erased && pixel.color.a == 0 {
+                        ERASED_COLOR
+                    } else {
+                        pixel.color
+                    };
                     chunk
                         .ugli
-                        .sub_image(pixel_position, vec2(1, 1), pixel.color.as_slice());
+                        .sub_image(pixel_position, vec2(1, 1), color.as_slice());
+                }
+                Update::Draw {
+                    layer,
+                    pixels: reverse,
                 }
-                Update::Draw(reverse)
             }
         }
     }
@@ -93,6 +134,7 @@ impl Infinite {
         &self,
         framebuffer: &mut ugli::Framebuffer,
         camera: &impl geng::AbstractCamera2d,
+        opacity: f32,
     ) -> Option<AABB<i32>> {
         let aabb = camera
             .view_area(framebuffer.size().map(|x| x as f32))
@@ -111,14 +153,15 @@ impl Infinite {
                     self.geng.draw_2d(
                         framebuffer,
                         camera,
-                        &draw_2d::TexturedQuad::new(
+                        &draw_2d::TexturedQuad::colored(
                             AABB::point(chunk_pos.map(|x| x as f32) * Self::CHUNK_SIZE as f32)
                                 .extend_positive(chunk.ugli.size().map(|x| x as f32)),
                             &chunk.ugli,
+                            Rgba::new(1.0, 1.0, 1.0, opacity),
                         ),
                     );
                 } else {
-                    if self.ignore_unloaded_updates {
+                    if self.show_unloaded {
                         self.geng.draw_2d(
                             framebuffer,
                             camera,
@@ -0,0 +1,187 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Draw {
+    Start(Vec2<f64>),
+    Move(Vec2<f64>),
+    End(Vec2<f64>),
+    // A second finger touched down, so the first one was not drawing after all
+    Cancel,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gesture {
+    Draw(Draw),
+    Pan { from: Vec2<f64>, to: Vec2<f64> },
+    Zoom { center: Vec2<f64>, factor: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Drawing { last: Vec2<f64> },
+    Pinching { center: Vec2<f64>, distance: f64 },
+    // After a pinch the remaining fingers do nothing until a new touch
+    Lifting,
+}
+
+// Turns touch events into drawing, two finger panning and pinch zooming,
+// only looks at the events so it works the same with recorded ones
+pub struct Gestures {
+    state: State,
+}
+
+impl Gestures {
+    pub fn new() -> Self {
+        Self { state: State::Idle }
+    }
+    fn pinch(touches: &[geng::TouchPoint]) -> (Vec2<f64>, f64) {
+        let a = touches[0].position;
+        let b = touches[1].position;
+        ((a + b) / 2.0, (a - b).len())
+    }
+    pub fn handle(&mut self, event: &geng::Event) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        match event {
+            geng::Event::TouchStart { touches } => match touches.len() {
+                0 => {}
+                1 => {
+                    if let State::Drawing { last } = self.state {
+                        gestures.push(Gesture::Draw(Draw::End(last)));
+                    }
+                    let position = touches[0].position;
+                    gestures.push(Gesture::Draw(Draw::Start(position)));
+                    self.state = State::Drawing { last: position };
+                }
+                _ => {
+                    if let State::Drawing { .. } = self.state {
+                        gestures.push(Gesture::Draw(Draw::Cancel));
+                    }
+                    let (center, distance) = Self::pinch(touches);
+                    self.state = State::Pinching { center, distance };
+                }
+            },
+            geng::Event::TouchMove { touches } => match self.state {
+                State::Drawing { .. } if !touches.is_empty() => {
+                    let position = touches[0].position;
+                    gestures.push(Gesture::Draw(Draw::Move(position)));
+                    self.state = State::Drawing { last: position };
+                }
+                State::Pinching { center, distance } if touches.len() >= 2 => {
+                    let (new_center, new_distance) = Self::pinch(touches);
+                    gestures.push(Gesture::Pan {
+                        from: center,
+                        to: new_center,
+                    });
+                    if distance > 0.0 && new_distance > 0.0 {
+                        gestures.push(Gesture::Zoom {
+                            center: new_center,
+                            factor: distance / new_distance,
+                        });
+                    }
+                    self.state = State::Pinching {
+                        center: new_center,
+                        distance: new_distance,
+                    };
+                }
+                _ => {}
+            },
+            geng::Event::TouchEnd { touches } => {
+                if let State::Drawing { last } = self.state {
+                    gestures.push(Gesture::Draw(Draw::End(last)));
+                }
+                self.state = if touches.is_empty() {
+                    State::Idle
+                } else {
+                    State::Lifting
+                };
+            }
+            _ => {}
+        }
+        gestures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touches(positions: &[(f64, f64)]) -> Vec<geng::TouchPoint> {
+        positions
+            .iter()
+            .map(|&(x, y)| geng::TouchPoint {
+                position: vec2(x, y),
+            })
+            .collect()
+    }
+
+    fn start(positions: &[(f64, f64)]) -> geng::Event {
+        geng::Event::TouchStart {
+            touches: touches(positions),
+        }
+    }
+
+    fn touch_move(positions: &[(f64, f64)]) -> geng::Event {
+        geng::Event::TouchMove {
+            touches: touches(positions),
+        }
+    }
+
+    fn end(positions: &[(f64, f64)]) -> geng::Event {
+        geng::Event::TouchEnd {
+            touches: touches(positions),
+        }
+    }
+
+    #[test]
+    fn one_finger_draws() {
+        let mut gestures = Gestures::new();
+        assert_eq!(
+            gestures.handle(&start(&[(1.0, 2.0)])),
+            vec![Gesture::Draw(Draw::Start(vec2(1.0, 2.0)))],
+        );
+        assert_eq!(
+            gestures.handle(&touch_move(&[(3.0, 4.0)])),
+            vec![Gesture::Draw(Draw::Move(vec2(3.0, 4.0)))],
+        );
+        assert_eq!(
+            gestures.handle(&end(&[])),
+            vec![Gesture::Draw(Draw::End(vec2(3.0, 4.0)))],
+        );
+        assert_eq!(gestures.state, State::Idle);
+    }
+
+    #[test]
+    fn second_finger_cancels_and_pinches() {
+        let mut gestures = Gestures::new();
+        gestures.handle(&start(&[(0.0, 0.0)]));
+        assert_eq!(
+            gestures.handle(&start(&[(0.0, 0.0), (10.0, 0.0)])),
+            vec![Gesture::Draw(Draw::Cancel)],
+        );
+        assert_eq!(
+            gestures.handle(&touch_move(&[(0.0, 10.0), (20.0, 10.0)])),
+            vec![
+                Gesture::Pan {
+                    from: vec2(5.0, 0.0),
+                    to: vec2(10.0, 10.0),
+                },
+                Gesture::Zoom {
+                    center: vec2(10.0, 10.0),
+                    factor: 0.5,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn lifting_after_pinch() {
+        let mut gestures = Gestures::new();
+        gestures.handle(&start(&[(0.0, 0.0), (10.0, 0.0)]));
+        assert_eq!(gestures.handle(&end(&[(0.0, 0.0)])), vec![]);
+        assert_eq!(gestures.state, State::Lifting);
+        assert_eq!(gestures.handle(&touch_move(&[(5.0, 5.0)])), vec![]);
+        assert_eq!(gestures.handle(&end(&[])), vec![]);
+        assert_eq!(gestures.state, State::Idle);
+    }
+}
@@ -146,6 +146,13 @@ impl<T: Default + Serialize + DeserializeOwned + Send + 'static> AutoSaved<T> {
     pub fn read(&self) -> ReadGuard<T> {
         ReadGuard { guard: self.lock() }
     }
+    // None instead of loading the value from disk
+    pub fn read_loaded(&self) -> Option<ReadGuard<T>> {
+        let mut guard = self.state.lock().unwrap();
+        guard.value.as_ref()?;
+        guard.touch();
+        Some(ReadGuard { guard })
+    }
     pub fn write(&self) -> WriteGuard<T> {
         let mut guard = self.lock();
         guard.mutated = true;
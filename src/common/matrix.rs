@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Matrix<T> {
     size: Vec2<usize>,
     data: Vec<T>,
@@ -19,9 +19,53 @@ impl<T> Matrix<T> {
             data: vec![value; size.x * size.y],
         }
     }
+    pub fn from_fn(size: Vec2<usize>, f: impl Fn(Vec2<usize>) -> T) -> Self {
+        let mut data = Vec::with_capacity(size.x * size.y);
+        for x in 0..size.x {
+            for y in 0..size.y {
+                data.push(f(vec2(x, y)));
+            }
+        }
+        Self { size, data }
+    }
     pub fn as_slice(&self) -> &[T] {
         self.data.as_slice()
     }
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.data.as_mut_slice()
+    }
+    pub fn flip_x(&self) -> Self
+    where
+        T: Clone,
+    {
+        let size = self.size;
+        Self::from_fn(size, |pos| self[vec2(size.x - 1 - pos.x, pos.y)].clone())
+    }
+    pub fn flip_y(&self) -> Self
+    where
+        T: Clone,
+    {
+        let size = self.size;
+        Self::from_fn(size, |pos| self[vec2(pos.x, size.y - 1 - pos.y)].clone())
+    }
+    // Counterclockwise by 90 degrees
+    pub fn rotate(&self) -> Self
+    where
+        T: Clone,
+    {
+        let size = self.size;
+        Self::from_fn(vec2(size.y, size.x), |pos| {
+            self[vec2(pos.y, size.y - 1 - pos.x)].clone()
+        })
+    }
+    // Nearest neighbor
+    pub fn scale(&self, factor: usize) -> Self
+    where
+        T: Clone,
+    {
+        assert!(factor > 0);
+        Self::from_fn(self.size * factor, |pos| self[pos / factor].clone())
+    }
 }
 
 impl<T> Index<Vec2<usize>> for Matrix<T> {
@@ -23,7 +23,63 @@ pub fn div_up<T: Num>(a: T, b: T) -> T {
     if b < T::ZERO {
         return -div_down(a, -b);
     }
-    (a + b - T::ONE) / b
+    // a + b - 1 could overflow
+    if a == T::ZERO {
+        return T::ZERO;
+    }
+    (a - T::ONE) / b + T::ONE
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Cooldown {
+    pub pixels: usize,
+    pub period: f64,
+}
+
+impl Cooldown {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pixels == 0 {
+            return Err("Cooldown must allow at least one pixel".to_owned());
+        }
+        if !self.period.is_finite() || self.period <= 0.0 {
+            return Err(format!("Invalid cooldown period {}", self.period));
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Cooldown {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (pixels, period) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Expected <pixels>/<seconds>, got {:?}", s))?;
+        let cooldown = Self {
+            pixels: pixels.trim().parse().map_err(|e| format!("{}", e))?,
+            period: period.trim().parse().map_err(|e| format!("{}", e))?,
+        };
+        cooldown.validate()?;
+        Ok(cooldown)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CanvasSettings {
+    pub cooldown: Option<Cooldown>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PixelBudget {
+    pub remaining: usize,
+    pub total: usize,
+    pub refill_in: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attribution {
+    pub author: String,
+    pub time: u64,
+    pub age: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,27 +88,279 @@ pub struct Pixel {
     pub color: Rgba<u8>,
 }
 
+pub fn blend(under: Rgba<u8>, over: Rgba<u8>) -> Rgba<u8> {
+    let under: Rgba<f32> = under.convert();
+    let over: Rgba<f32> = over.convert();
+    let a = over.a + under.a * (1.0 - over.a);
+    if a == 0.0 {
+        return Rgba::TRANSPARENT_BLACK;
+    }
+    let channel = |over_value: f32, under_value: f32| {
+        (over_value * over.a + under_value * under.a * (1.0 - over.a)) / a
+    };
+    Rgba::new(
+        channel(over.r, under.r),
+        channel(over.g, under.g),
+        channel(over.b, under.b),
+        a,
+    )
+    .convert()
+}
+
+pub fn blend_layer(target: &mut Matrix<Rgba<u8>>, layer: &Matrix<Rgba<u8>>) {
+    assert_eq!(target.size(), layer.size());
+    for (under, &over) in target.as_mut_slice().iter_mut().zip(layer.as_slice()) {
+        *under = blend(*under, over);
+    }
+}
+
+// Image rows go down from the top, matrix rows go up from the bottom
+#[cfg(not(target_arch = "wasm32"))]
+pub fn matrix_from_image(image: &image::RgbaImage) -> Matrix<Rgba<u8>> {
+    let size = vec2(image.width() as usize, image.height() as usize);
+    Matrix::from_fn(size, |pos| {
+        let image::Rgba([r, g, b, a]) = *image.get_pixel(pos.x as u32, (size.y - 1 - pos.y) as u32);
+        Rgba::new(r, g, b, a)
+    })
+}
+
+pub const BASE_LAYER: &str = "base";
+
+pub const MAX_LAYERS: usize = 16;
+
+// Used for names that end up in file paths: canvases, layers, ...
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// The bottom left corner of the stamp goes to the position, transparent pixels are skipped
+pub fn stamp_update(layer: String, image: &Matrix<Rgba<u8>>, position: Vec2<i32>) -> Update {
+    let mut pixels = Vec::new();
+    for x in 0..image.size().x {
+        for y in 0..image.size().y {
+            let color = image[vec2(x, y)];
+            if color.a != 0 {
+                pixels.push(Pixel {
+                    position: position + vec2(x, y).map(|x| x as i32),
+                    color,
+                });
+            }
+        }
+    }
+    Update::Draw { layer, pixels }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Update {
-    Draw(Vec<Pixel>),
+    Draw { layer: String, pixels: Vec<Pixel> },
+}
+
+impl Update {
+    pub fn layer(&self) -> &str {
+        match self {
+            Self::Draw { layer, .. } => layer,
+        }
+    }
+    pub fn pixel_count(&self) -> usize {
+        match self {
+            Self::Draw { pixels, .. } => pixels.len(),
+        }
+    }
 }
 
 pub type UpdateId = u64;
 
+pub type ClientId = u64;
+
+pub const DEFAULT_CANVAS: &str = "main";
+
+pub const ADMIN_GROUP: &str = "admin";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+impl User {
+    pub fn is_in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g == group)
+    }
+}
+
+// Editors are user names or @group names, a region without editors is read-only
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Region {
+    pub area: AABB<i32>,
+    pub editors: Vec<String>,
+}
+
+impl Region {
+    pub fn is_editable_by(&self, user: Option<&User>) -> bool {
+        let user = match user {
+            Some(user) => user,
+            None => return false,
+        };
+        user.is_in_group(ADMIN_GROUP)
+            || self
+                .editors
+                .iter()
+                .any(|editor| match editor.strip_prefix('@') {
+                    Some(group) => user.is_in_group(group),
+                    None => *editor == user.name,
+                })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Bookmark {
+    pub name: String,
+    pub center: Vec2<f32>,
+    pub fov: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateInfo {
+    pub name: String,
+    pub position: Vec2<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
-    Download { area: AABB<i32> },
-    Update { id: UpdateId, update: Update },
+    Authenticate {
+        token: String,
+    },
+    Join {
+        canvas: String,
+    },
+    Download {
+        layer: String,
+        area: AABB<i32>,
+    },
+    AddLayer {
+        name: String,
+    },
+    Update {
+        id: UpdateId,
+        update: Update,
+    },
+    Preview(Update),
+    // The previewed stroke was dropped without an update
+    PreviewEnd,
+    AddRegion(Region),
+    RemoveRegion(AABB<i32>),
+    SetCooldown(Option<Cooldown>),
+    Attribution {
+        layer: String,
+        position: Vec2<i32>,
+    },
+    HistoryRange,
+    DownloadHistory {
+        area: AABB<i32>,
+        time: u64,
+    },
+    AddStamp {
+        name: String,
+        image: Matrix<Rgba<u8>>,
+    },
+    DownloadStamp {
+        name: String,
+    },
+    PlaceStamp {
+        id: UpdateId,
+        layer: String,
+        name: String,
+        position: Vec2<i32>,
+    },
+    DownloadTemplate {
+        name: String,
+    },
+    Overview {
+        center: Vec2<i32>,
+    },
+    Cursor(Vec2<i32>),
+    AddBookmark(Bookmark),
+    RemoveBookmark(String),
+    // Answered with a Pong, so a silent connection can be told apart from a dropped one
+    Ping,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ServerMessage {
+    // Sent on connect, bigger updates are rejected and so are updates over the rate
+    UpdateLimits {
+        max_pixels: usize,
+        pixels_per_second: f64,
+    },
+    Layers(Vec<String>),
     Download {
+        layer: String,
         position: Vec2<i32>,
         data: Matrix<Rgba<u8>>,
     },
+    Authenticated {
+        user: User,
+    },
     Update {
         your_id: Option<UpdateId>,
+        author: Option<String>,
         update: Update,
     },
+    Preview {
+        client_id: ClientId,
+        update: Update,
+    },
+    PreviewEnd {
+        client_id: ClientId,
+    },
+    Regions(Vec<Region>),
+    PixelBudget(Option<PixelBudget>),
+    Attribution {
+        position: Vec2<i32>,
+        attribution: Option<Attribution>,
+    },
+    HistoryRange(Option<(u64, u64)>),
+    DownloadHistory {
+        position: Vec2<i32>,
+        time: u64,
+        data: Matrix<Rgba<u8>>,
+    },
+    DownloadRejected {
+        reason: String,
+    },
+    Stamps(Vec<String>),
+    Stamp {
+        name: String,
+        image: Matrix<Rgba<u8>>,
+    },
+    // Sent instead of an update with the stamp pixels, after the Stamp if the client did not have it
+    PlaceStamp {
+        your_id: Option<UpdateId>,
+        author: Option<String>,
+        layer: String,
+        name: String,
+        position: Vec2<i32>,
+    },
+    Templates(Vec<TemplateInfo>),
+    Bookmarks(Vec<Bookmark>),
+    // Every pixel of the data covers the same part of the area
+    Overview {
+        area: AABB<i32>,
+        data: Matrix<Rgba<u8>>,
+    },
+    Cursor {
+        client_id: ClientId,
+        author: Option<String>,
+        position: Option<Vec2<i32>>,
+    },
+    Template {
+        info: TemplateInfo,
+        image: Matrix<Rgba<u8>>,
+    },
+    Error(String),
+    Pong,
 }
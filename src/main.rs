@@ -11,17 +11,134 @@ use common::*;
 #[cfg(not(target_arch = "wasm32"))]
 use server::Server;
 
+// `off` or <pixels>/<seconds>
+#[derive(Debug, Clone, Copy)]
+pub struct CooldownSetting(Option<Cooldown>);
+
+fn parse_cooldown_setting(s: &str) -> Result<CooldownSetting, String> {
+    match s {
+        "off" => Ok(CooldownSetting(None)),
+        s => s.parse().map(|cooldown| CooldownSetting(Some(cooldown))),
+    }
+}
+
+// x,y
+fn parse_position(s: &str) -> Result<Vec2<i32>, String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Expected x,y, got {:?}", s))?;
+    Ok(vec2(
+        x.trim().parse().map_err(|e| format!("{}", e))?,
+        y.trim().parse().map_err(|e| format!("{}", e))?,
+    ))
+}
+
+fn parse_area(s: &str) -> Result<AABB<i32>, String> {
+    let coordinates = s
+        .split(',')
+        .map(|x| x.trim().parse().map_err(|e| format!("{}", e)))
+        .collect::<Result<Vec<i32>, String>>()?;
+    let area = match coordinates.as_slice() {
+        &[x_min, y_min, x_max, y_max] => AABB {
+            x_min,
+            y_min,
+            x_max,
+            y_max,
+        },
+        _ => return Err(format!("Expected x_min,y_min,x_max,y_max, got {:?}", s)),
+    };
+    if area.x_max <= area.x_min || area.y_max <= area.y_min {
+        return Err(format!("Area {:?} is empty", s));
+    }
+    Ok(area)
+}
+
+// Steps are counted in whole milliseconds
+fn parse_step(s: &str) -> Result<f64, String> {
+    let step: f64 = s.trim().parse().map_err(|e| format!("{}", e))?;
+    if !step.is_finite() || step < 0.001 {
+        return Err(format!(
+            "Step should be at least 0.001 seconds, got {:?}",
+            s
+        ));
+    }
+    Ok(step)
+}
+
 #[derive(clap::Parser, Clone)]
 pub struct Opt {
     #[clap(long)]
     server: Option<String>,
     #[clap(long)]
     connect: Option<String>,
+    #[clap(long)]
+    users: Option<std::path::PathBuf>,
+    #[clap(long, default_value = "20000")]
+    max_pixels_per_second: f64,
+    #[clap(long, default_value = "20000")]
+    max_update_pixels: usize,
+    #[clap(long, default_value = "262144")]
+    max_download_area: usize,
+    #[clap(long, default_value = "50")]
+    max_downloads_per_second: f64,
+    #[clap(long, default_value = "20")]
+    max_violations: f64,
+    #[clap(long, default_value = "1000")]
+    max_canvases: usize,
+    #[clap(long)]
+    token: Option<String>,
+    #[clap(long, value_parser = parse_cooldown_setting)]
+    set_cooldown: Option<CooldownSetting>,
+    #[clap(long, default_value = DEFAULT_CANVAS)]
+    canvas: String,
+    #[clap(long)]
+    stream_strokes: bool,
+    #[clap(long)]
+    rebuild_from_log: Option<String>,
+    #[clap(long)]
+    verify_log: Option<String>,
+    #[clap(long)]
+    timelapse: Option<String>,
+    #[clap(long, default_value = "-128,-128,128,128", value_parser = parse_area)]
+    timelapse_area: AABB<i32>,
+    #[clap(long, default_value = "60", value_parser = parse_step)]
+    timelapse_step: f64,
+    #[clap(long, default_value = "timelapse")]
+    timelapse_output: std::path::PathBuf,
+    #[clap(long)]
+    timelapse_gif: bool,
+    #[clap(long)]
+    template: Option<std::path::PathBuf>,
+    #[clap(long, default_value = "0,0", value_parser = parse_position)]
+    template_position: Vec2<i32>,
+    #[clap(long)]
+    x: Option<f32>,
+    #[clap(long)]
+    y: Option<f32>,
+    #[clap(long)]
+    fov: Option<f32>,
 }
 
 fn main() {
     logger::init().unwrap();
     let mut opt: Opt = program_args::parse();
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(canvas) = &opt.rebuild_from_log {
+            server::oplog::rebuild(canvas);
+            return;
+        }
+        if let Some(canvas) = &opt.verify_log {
+            if !server::oplog::verify(canvas) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        if let Some(canvas) = &opt.timelapse {
+            server::timelapse::run(canvas, server::timelapse::Options::new(&opt));
+            return;
+        }
+    }
     if opt.connect.is_none() && opt.server.is_none() {
         if cfg!(target_arch = "wasm32") {
             opt.connect = Some(
@@ -36,11 +153,11 @@ fn main() {
     }
     if opt.server.is_some() && opt.connect.is_none() {
         #[cfg(not(target_arch = "wasm32"))]
-        geng::net::Server::new(Server::new(), opt.server.as_deref().unwrap()).run();
+        geng::net::Server::new(Server::new(&opt), opt.server.as_deref().unwrap()).run();
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if let Some(addr) = &opt.server {
-            let server = geng::net::Server::new(Server::new(), addr);
+            let server = geng::net::Server::new(Server::new(&opt), addr);
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -62,7 +179,8 @@ fn main() {
             geng::net::client::connect(opt.connect.as_deref().unwrap()),
             {
                 let geng = geng.clone();
-                move |connection| Client::new(&geng, connection)
+                let opt = opt.clone();
+                move |connection| Client::new(&geng, &opt, connection)
             },
         );
         geng::run(&geng, state);
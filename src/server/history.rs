@@ -0,0 +1,197 @@
+use super::*;
+
+// Snapshots replay the log which takes a while, so they are made by these threads
+// without holding the state lock
+const THREADS: usize = 4;
+
+// Layers of an area at a checkpoint, kept for scrubbing back and forth,
+// the oldest ones are dropped first
+const MAX_CACHED_STATES: usize = 256;
+
+pub struct Request {
+    pub client_id: ClientId,
+    pub canvas: String,
+    // Offsets of the checkpoints with only earlier entries before them
+    pub checkpoints: Vec<u64>,
+    pub log_len: u64,
+    pub layers: Vec<String>,
+    pub area: AABB<i32>,
+    pub time: u64,
+}
+
+// Clients are served in turn, so one client scrubbing through a long history
+// does not hold up everyone else
+#[derive(Default)]
+struct Queue {
+    requests: HashMap<ClientId, VecDeque<Request>>,
+    turns: VecDeque<ClientId>,
+    closed: bool,
+}
+
+impl Queue {
+    fn push(&mut self, request: Request) {
+        let requests = self.requests.entry(request.client_id).or_default();
+        if requests.is_empty() {
+            self.turns.push_back(request.client_id);
+        }
+        requests.push_back(request);
+    }
+    fn pop(&mut self) -> Option<Request> {
+        let client_id = self.turns.pop_front()?;
+        let requests = self.requests.get_mut(&client_id).unwrap();
+        let request = requests.pop_front().unwrap();
+        if requests.is_empty() {
+            self.requests.remove(&client_id);
+        } else {
+            self.turns.push_back(client_id);
+        }
+        Some(request)
+    }
+    fn forget(&mut self, client_id: ClientId) {
+        self.requests.remove(&client_id);
+        self.turns.retain(|&id| id != client_id);
+    }
+}
+
+type CacheKey = (String, [i32; 4], u64);
+
+#[derive(Default)]
+struct Cache {
+    states: HashMap<CacheKey, oplog::AreaState>,
+    order: VecDeque<CacheKey>,
+}
+
+impl Cache {
+    fn insert(&mut self, key: CacheKey, state: oplog::AreaState) {
+        if self.states.insert(key.clone(), state).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > MAX_CACHED_STATES {
+            let oldest = self.order.pop_front().unwrap();
+            self.states.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: std::sync::Condvar,
+    cache: Mutex<Cache>,
+}
+
+pub struct Workers {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl Workers {
+    pub fn new(state: std::sync::Weak<Mutex<ServerState>>) -> Self {
+        let shared = Arc::new(Shared::default());
+        for _ in 0..THREADS {
+            let shared = shared.clone();
+            let state = state.clone();
+            std::thread::spawn(move || worker(&shared, &state));
+        }
+        Self { shared }
+    }
+    pub fn push(&self, request: Request) {
+        self.shared.queue.lock().unwrap().push(request);
+        self.shared.ready.notify_one();
+    }
+    // Requests of a client that left are not worth making
+    pub fn forget(&self, client_id: ClientId) {
+        self.shared.queue.lock().unwrap().forget(client_id);
+    }
+}
+
+fn next_request(shared: &Shared) -> Option<Request> {
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        if queue.closed {
+            return None;
+        }
+        if let Some(request) = queue.pop() {
+            return Some(request);
+        }
+        queue = shared.ready.wait(queue).unwrap();
+    }
+}
+
+// Starts from the latest cached checkpoint and caches the later ones on the way
+fn snapshot(cache: &Mutex<Cache>, request: &Request) -> Matrix<Rgba<u8>> {
+    let area = request.area;
+    let key = |offset| {
+        let corners = [area.x_min, area.y_min, area.x_max, area.y_max];
+        (request.canvas.clone(), corners, offset)
+    };
+    let cached = {
+        let cache = cache.lock().unwrap();
+        request
+            .checkpoints
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, &offset)| {
+                let state = cache.states.get(&key(offset))?;
+                Some((i + 1, offset, state.clone()))
+            })
+    };
+    let (first_uncached, mut start, mut state) = cached.unwrap_or((0, 0, default()));
+    for &offset in &request.checkpoints[first_uncached..] {
+        oplog::replay_area(
+            &request.canvas,
+            &mut state,
+            area,
+            start,
+            offset,
+            request.time,
+        );
+        cache.lock().unwrap().insert(key(offset), state.clone());
+        start = offset;
+    }
+    oplog::replay_area(
+        &request.canvas,
+        &mut state,
+        area,
+        start,
+        request.log_len,
+        request.time,
+    );
+    oplog::compose(&state, &request.layers, area)
+}
+
+fn worker(shared: &Shared, state: &std::sync::Weak<Mutex<ServerState>>) {
+    while let Some(request) = next_request(shared) {
+        let data = snapshot(&shared.cache, &request);
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut state = state.lock().unwrap();
+        // The client may have left or switched canvas in the meantime
+        let still_there = state
+            .clients
+            .get(&request.client_id)
+            .map_or(false, |client| {
+                client.canvas.as_deref() == Some(request.canvas.as_str())
+            });
+        if still_there {
+            state.send(
+                request.client_id,
+                ServerMessage::DownloadHistory {
+                    position: request.area.bottom_left(),
+                    time: request.time,
+                    data,
+                },
+            );
+        }
+    }
+}
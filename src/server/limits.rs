@@ -0,0 +1,160 @@
+use super::*;
+
+const CURSOR_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+// Far enough from the ends of i32 that chunk math on requested positions can not overflow
+const MAX_COORDINATE: i32 = i32::MAX / 2;
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub pixels_per_second: f64,
+    pub max_update_pixels: usize,
+    pub max_download_area: usize,
+    pub downloads_per_second: f64,
+    pub max_violations: f64,
+    pub max_canvases: usize,
+}
+
+impl Limits {
+    pub fn new(opt: &Opt) -> Self {
+        // A bigger update could never be accepted by the rate limiter
+        let max_update_pixels = if opt.max_update_pixels as f64 > opt.max_pixels_per_second {
+            warn!(
+                "--max-update-pixels is above --max-pixels-per-second, using {}",
+                opt.max_pixels_per_second,
+            );
+            opt.max_pixels_per_second as usize
+        } else {
+            opt.max_update_pixels
+        };
+        Self {
+            pixels_per_second: opt.max_pixels_per_second,
+            max_update_pixels,
+            max_download_area: opt.max_download_area,
+            downloads_per_second: opt.max_downloads_per_second,
+            max_violations: opt.max_violations,
+            max_canvases: opt.max_canvases,
+        }
+    }
+}
+
+// Budgets refill continuously up to one second worth of requests.
+// Spending is allowed while the budget is positive so a single big update never gets stuck.
+pub struct RateLimiter {
+    last_refill: std::time::Instant,
+    pixels: f64,
+    preview_pixels: f64,
+    downloads: f64,
+    violations: f64,
+    last_cursor: Option<std::time::Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            last_refill: std::time::Instant::now(),
+            pixels: limits.pixels_per_second,
+            preview_pixels: limits.pixels_per_second,
+            downloads: limits.downloads_per_second,
+            violations: 0.0,
+            last_cursor: None,
+        }
+    }
+    fn refill(&mut self, limits: &Limits) {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.last_refill = std::time::Instant::now();
+        self.pixels =
+            (self.pixels + elapsed * limits.pixels_per_second).min(limits.pixels_per_second);
+        self.preview_pixels = (self.preview_pixels + elapsed * limits.pixels_per_second)
+            .min(limits.pixels_per_second);
+        self.downloads = (self.downloads + elapsed * limits.downloads_per_second)
+            .min(limits.downloads_per_second);
+        self.violations = (self.violations - elapsed).max(0.0);
+    }
+    // Updates and uploaded stamps share the pixel budget
+    pub fn check_pixels(&mut self, limits: &Limits, pixels: usize) -> Result<(), String> {
+        check_size(limits, pixels)?;
+        self.refill(limits);
+        spend(&mut self.pixels, pixels)
+    }
+    pub fn check_update(&mut self, limits: &Limits, update: &Update) -> Result<(), String> {
+        self.check_pixels(limits, update.pixel_count())
+    }
+    // Previewed pixels are sent again in the update, so previews have a budget of their own
+    pub fn check_preview(&mut self, limits: &Limits, update: &Update) -> Result<(), String> {
+        check_size(limits, update.pixel_count())?;
+        self.refill(limits);
+        spend(&mut self.preview_pixels, update.pixel_count())
+    }
+    pub fn check_download(&mut self, limits: &Limits, area: AABB<i32>) -> Result<(), String> {
+        let corners = [area.bottom_left(), vec2(area.x_max, area.y_max)];
+        if corners
+            .into_iter()
+            .any(|corner| check_position(corner).is_err())
+            || area.width() <= 0
+            || area.height() <= 0
+        {
+            return Err(format!("Invalid download area {:?}", area));
+        }
+        // The sides fit into i32 with the corners that close to the origin
+        let size = area.width() as u64 * area.height() as u64;
+        if size > limits.max_download_area as u64 {
+            return Err(format!(
+                "Download area is too big ({} > {} pixels)",
+                size, limits.max_download_area,
+            ));
+        }
+        self.check_request(limits)
+    }
+    // Small requests share the download budget
+    pub fn check_request(&mut self, limits: &Limits) -> Result<(), String> {
+        self.refill(limits);
+        if self.downloads < 1.0 {
+            return Err("Downloading too fast, slow down".to_owned());
+        }
+        self.downloads -= 1.0;
+        Ok(())
+    }
+    pub fn check_cursor(&mut self) -> Result<(), String> {
+        if self
+            .last_cursor
+            .map_or(false, |time| time.elapsed() < CURSOR_INTERVAL)
+        {
+            return Err("Moving the cursor too fast".to_owned());
+        }
+        self.last_cursor = Some(std::time::Instant::now());
+        Ok(())
+    }
+    // Returns true when the client should be disconnected
+    pub fn violation(&mut self, limits: &Limits) -> bool {
+        self.refill(limits);
+        self.violations += 1.0;
+        self.violations > limits.max_violations
+    }
+}
+
+pub fn check_position(position: Vec2<i32>) -> Result<(), String> {
+    if position.x.unsigned_abs() > MAX_COORDINATE as u32
+        || position.y.unsigned_abs() > MAX_COORDINATE as u32
+    {
+        return Err(format!("Position {:?} is too far away", position));
+    }
+    Ok(())
+}
+
+fn check_size(limits: &Limits, pixels: usize) -> Result<(), String> {
+    if pixels > limits.max_update_pixels {
+        return Err(format!(
+            "Update is too big ({} > {} pixels)",
+            pixels, limits.max_update_pixels,
+        ));
+    }
+    Ok(())
+}
+
+fn spend(budget: &mut f64, pixels: usize) -> Result<(), String> {
+    if *budget <= 0.0 {
+        return Err("Drawing too fast, slow down".to_owned());
+    }
+    *budget -= pixels as f64;
+    Ok(())
+}
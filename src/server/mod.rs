@@ -1,50 +1,924 @@
 use super::*;
 
+mod history;
+mod limits;
+pub mod oplog;
+mod stamps;
+mod templates;
 mod texture;
+pub mod timelapse;
+mod users;
 
-type ClientId = u64;
-type ClientState = Box<dyn geng::net::Sender<ServerMessage>>;
+const SAVE_DIR: &str = "save";
+
+const ANONYMOUS: &str = "anonymous";
+
+// Chunks in every direction from the center of an overview
+const OVERVIEW_RADIUS: i32 = 8;
+
+const MAX_BOOKMARKS: usize = 256;
+
+fn unix_time_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn canvas_path(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(SAVE_DIR).join(name)
+}
+
+// The base layer lives directly in the canvas dir, same as before there were layers
+fn layer_path(canvas_dir: &std::path::Path, layer: &str) -> std::path::PathBuf {
+    if layer == BASE_LAYER {
+        canvas_dir.to_owned()
+    } else {
+        canvas_dir.join("layers").join(layer)
+    }
+}
+
+fn layer_order_path(canvas: &str) -> std::path::PathBuf {
+    canvas_path(canvas).join("layer_order")
+}
+
+// Layers from bottom to top
+fn saved_layer_names(canvas: &str) -> Vec<String> {
+    let order = AutoSaved::<Vec<String>>::new(layer_order_path(canvas));
+    let names = order.read().clone();
+    std::iter::once(BASE_LAYER.to_owned())
+        .chain(names)
+        .collect()
+}
+
+struct ClientState {
+    sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    user: Option<User>,
+    canvas: Option<String>,
+    limiter: limits::RateLimiter,
+    // Stamp images the client has, placed stamps are sent to it by name
+    stamps: HashSet<String>,
+}
+
+struct Canvas {
+    clients: HashSet<ClientId>,
+    previewing: HashSet<ClientId>,
+    regions: AutoSaved<Vec<Region>>,
+    bookmarks: AutoSaved<Vec<Bookmark>>,
+    settings: AutoSaved<CanvasSettings>,
+    log: oplog::Log,
+    path: std::path::PathBuf,
+    layer_order: AutoSaved<Vec<String>>,
+    layers: HashMap<String, texture::Infinite>,
+    stamps: stamps::Stamps,
+    templates: templates::Templates,
+}
+
+impl Canvas {
+    fn new(name: &str, log_index: Option<oplog::Index>) -> std::io::Result<Self> {
+        let path = canvas_path(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self {
+            clients: default(),
+            previewing: default(),
+            log: oplog::Log::open(oplog::path(name), log_index)?,
+            layer_order: AutoSaved::new(layer_order_path(name)),
+            layers: default(),
+            regions: AutoSaved::new(path.join("regions")),
+            bookmarks: AutoSaved::new(path.join("bookmarks")),
+            settings: AutoSaved::new(path.join("settings")),
+            stamps: stamps::Stamps::new(path.join("stamps")),
+            templates: templates::Templates::new(path.join("templates")),
+            path,
+        })
+    }
+    fn layer_names(&self) -> Vec<String> {
+        std::iter::once(BASE_LAYER.to_owned())
+            .chain(self.layer_order.read().iter().cloned())
+            .collect()
+    }
+    // Settings saved before cooldowns were validated are ignored instead of trusted
+    fn cooldown(&self) -> Option<Cooldown> {
+        let cooldown = self.settings.read().cooldown?;
+        match cooldown.validate() {
+            Ok(()) => Some(cooldown),
+            Err(error) => {
+                warn!("Ignoring cooldown of canvas {:?}: {}", self.path, error);
+                None
+            }
+        }
+    }
+    fn has_layer(&self, name: &str) -> bool {
+        name == BASE_LAYER || self.layer_order.read().iter().any(|layer| layer == name)
+    }
+    fn layer(&mut self, name: &str) -> Option<&mut texture::Infinite> {
+        if !self.has_layer(name) {
+            return None;
+        }
+        Some(
+            self.layers
+                .entry(name.to_owned())
+                .or_insert_with(|| texture::Infinite::new(layer_path(&self.path, name))),
+        )
+    }
+    // Low resolution composite of the chunks around the center,
+    // chunks that are not in memory show up in a later overview
+    fn overview(&mut self, center: Vec2<i32>) -> (AABB<i32>, Matrix<Rgba<u8>>) {
+        let chunk_size = texture::CHUNK_SIZE as i32;
+        let overview_chunk_size = texture::CHUNK_SIZE / texture::OVERVIEW_SCALE;
+        let chunks =
+            AABB::point(center.map(|x| div_down(x, chunk_size))).extend_uniform(OVERVIEW_RADIUS);
+        let size = chunks.size().map(|x| x as usize * overview_chunk_size);
+        let mut result = Matrix::filled_with(size, Rgba::TRANSPARENT_BLACK);
+        for name in self.layer_names() {
+            let layer = self.layer(&name).unwrap();
+            let mut layer_result = Matrix::filled_with(size, Rgba::TRANSPARENT_BLACK);
+            for (chunk_pos, overview) in layer.overviews(chunks) {
+                let origin =
+                    (chunk_pos - chunks.bottom_left()).map(|x| x as usize * overview_chunk_size);
+                for x in 0..overview_chunk_size {
+                    for y in 0..overview_chunk_size {
+                        layer_result[origin + vec2(x, y)] = overview[vec2(x, y)];
+                    }
+                }
+            }
+            blend_layer(&mut result, &layer_result);
+        }
+        (chunks.map(|x| x * chunk_size), result)
+    }
+    fn drop_protected(&self, update: Update, user: Option<&User>) -> (Update, bool) {
+        let regions = self.regions.read();
+        match update {
+            Update::Draw { layer, pixels } => {
+                let total = pixels.len();
+                let pixels: Vec<Pixel> = pixels
+                    .into_iter()
+                    .filter(|pixel| {
+                        regions.iter().all(|region| {
+                            !region.area.contains(pixel.position) || region.is_editable_by(user)
+                        })
+                    })
+                    .collect();
+                let dropped = pixels.len() < total;
+                (Update::Draw { layer, pixels }, dropped)
+            }
+        }
+    }
+}
+
+// Chunks used to be saved directly into the save dir before there were multiple canvases
+fn migrate_single_canvas_save() {
+    let save_dir = std::path::Path::new(SAVE_DIR);
+    let target = save_dir.join(DEFAULT_CANVAS);
+    if target.exists() {
+        return;
+    }
+    let entries = match std::fs::read_dir(save_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        let path = entry.expect("Failed to read save dir").path();
+        if path.extension().map_or(false, |ext| ext == "chunk") {
+            std::fs::create_dir_all(&target).expect("Failed to create canvas dir");
+            std::fs::rename(&path, target.join(path.file_name().unwrap()))
+                .expect("Failed to move chunk");
+        }
+    }
+}
+
+struct Budget {
+    remaining: usize,
+    refill_at: Option<std::time::Instant>,
+}
 
 struct ServerState {
     next_client_id: ClientId,
     clients: HashMap<ClientId, ClientState>,
-    state: texture::Infinite,
+    // Disconnected for violations but the socket is still open
+    kicked: HashSet<ClientId>,
+    canvases: HashMap<String, Canvas>,
+    // Of closed canvases
+    log_indexes: HashMap<String, oplog::Index>,
+    users: Option<users::Users>,
+    limits: limits::Limits,
+    budgets: HashMap<(String, String), Budget>,
+    history: history::Workers,
 }
 
 impl ServerState {
-    fn new() -> Self {
+    fn new(opt: &Opt, history: history::Workers) -> Self {
+        migrate_single_canvas_save();
         Self {
             next_client_id: 0,
             clients: default(),
-            state: texture::Infinite::new("save"),
+            kicked: default(),
+            canvases: default(),
+            log_indexes: default(),
+            users: opt.users.as_ref().map(users::Users::new),
+            limits: limits::Limits::new(opt),
+            budgets: default(),
+            history,
+        }
+    }
+    fn send(&mut self, client_id: ClientId, message: ServerMessage) {
+        self.clients
+            .get_mut(&client_id)
+            .unwrap()
+            .sender
+            .send(message);
+    }
+    fn broadcast(&mut self, canvas_name: &str, message: ServerMessage) {
+        for client_id in &self.canvases[canvas_name].clients {
+            self.clients
+                .get_mut(client_id)
+                .unwrap()
+                .sender
+                .send(message.clone());
+        }
+    }
+    fn authenticate(&mut self, client_id: ClientId, token: &str) {
+        let user = match &self.users {
+            Some(users) => users.authenticate(token),
+            None => None,
+        };
+        match user {
+            Some(user) => {
+                info!("Client #{} logged in as {:?}", client_id, user.name);
+                self.send(
+                    client_id,
+                    ServerMessage::Authenticated { user: user.clone() },
+                );
+                self.clients.get_mut(&client_id).unwrap().user = Some(user);
+            }
+            None => {
+                self.send(
+                    client_id,
+                    ServerMessage::Error("Authentication failed".to_owned()),
+                );
+                self.violation(client_id);
+            }
+        }
+    }
+    fn can_edit(&self, client_id: ClientId) -> bool {
+        self.users.is_none() || self.clients[&client_id].user.is_some()
+    }
+    fn reject_update(&mut self, client_id: ClientId, id: UpdateId, reason: String) {
+        self.send(client_id, ServerMessage::Error(reason));
+        // Confirming an empty update makes the client revert its optimistic changes
+        self.send(
+            client_id,
+            ServerMessage::Update {
+                your_id: Some(id),
+                author: None,
+                update: Update::Draw {
+                    layer: BASE_LAYER.to_owned(),
+                    pixels: vec![],
+                },
+            },
+        );
+        // Others would see the preview of the rejected stroke until the next accepted update
+        if let Some(canvas_name) = self.clients[&client_id].canvas.clone() {
+            self.end_preview(&canvas_name, client_id);
+        }
+    }
+    fn violation(&mut self, client_id: ClientId) {
+        let client = self.clients.get_mut(&client_id).unwrap();
+        if client.limiter.violation(&self.limits) {
+            warn!(
+                "Disconnecting client #{} for too many rejected requests",
+                client_id
+            );
+            self.send(
+                client_id,
+                ServerMessage::Error("Too many rejected requests, disconnecting".to_owned()),
+            );
+            self.leave(client_id);
+            // geng::net senders can not close the socket, so the client state (and its sender)
+            // is dropped and everything the client sends until it goes away is ignored
+            self.clients.remove(&client_id);
+            self.kicked.insert(client_id);
         }
     }
+    fn check_limits(&mut self, client_id: ClientId, message: &ClientMessage) -> bool {
+        let client = self.clients.get_mut(&client_id).unwrap();
+        let result = match message {
+            ClientMessage::Download { area, .. } | ClientMessage::DownloadHistory { area, .. } => {
+                client.limiter.check_download(&self.limits, *area)
+            }
+            ClientMessage::Update { update, .. } => {
+                client.limiter.check_update(&self.limits, update)
+            }
+            ClientMessage::Preview(update) => client.limiter.check_preview(&self.limits, update),
+            ClientMessage::AddStamp { image, .. } => client
+                .limiter
+                .check_pixels(&self.limits, image.as_slice().len()),
+            ClientMessage::Cursor(_) => client.limiter.check_cursor(),
+            ClientMessage::Overview { center } => limits::check_position(*center)
+                .and_then(|()| client.limiter.check_request(&self.limits)),
+            ClientMessage::Attribution { .. }
+            | ClientMessage::HistoryRange
+            | ClientMessage::PreviewEnd
+            | ClientMessage::DownloadStamp { .. }
+            | ClientMessage::DownloadTemplate { .. }
+            | ClientMessage::AddLayer { .. }
+            | ClientMessage::AddRegion(_)
+            | ClientMessage::RemoveRegion(_)
+            | ClientMessage::SetCooldown(_)
+            | ClientMessage::AddBookmark(_)
+            | ClientMessage::RemoveBookmark(_)
+            | ClientMessage::Ping => client.limiter.check_request(&self.limits),
+            // Handled before limits are checked
+            ClientMessage::Authenticate { .. }
+            | ClientMessage::Join { .. }
+            | ClientMessage::PlaceStamp { .. } => Ok(()),
+        };
+        let reason = match result {
+            Ok(()) => return true,
+            Err(reason) => reason,
+        };
+        match message {
+            // Honest clients hit these while drawing fast, dropping them is enough
+            ClientMessage::Preview(_) | ClientMessage::Cursor(_) => return false,
+            _ => {}
+        }
+        warn!("Rejected request from client #{}: {}", client_id, reason);
+        match message {
+            ClientMessage::Download { .. } | ClientMessage::DownloadHistory { .. } => {
+                self.send(client_id, ServerMessage::DownloadRejected { reason });
+            }
+            ClientMessage::Update { id, .. } => self.reject_update(client_id, *id, reason),
+            _ => self.send(client_id, ServerMessage::Error(reason)),
+        }
+        self.violation(client_id);
+        false
+    }
+    // Budgets are kept per user rather than per connection so reconnecting does not refill them,
+    // anonymous clients could refill theirs by reconnecting so they can not draw with a cooldown
+    fn budget(&mut self, canvas_name: &str, user: String, cooldown: Cooldown) -> &mut Budget {
+        let budget = self
+            .budgets
+            .entry((canvas_name.to_owned(), user))
+            .or_insert(Budget {
+                remaining: cooldown.pixels,
+                refill_at: None,
+            });
+        if budget
+            .refill_at
+            .map_or(false, |at| at <= std::time::Instant::now())
+        {
+            budget.remaining = cooldown.pixels;
+            budget.refill_at = None;
+        }
+        budget.remaining = budget.remaining.min(cooldown.pixels);
+        budget
+    }
+    fn pixel_budget(&mut self, canvas_name: &str, client_id: ClientId) -> Option<PixelBudget> {
+        let cooldown = self.canvases[canvas_name].cooldown()?;
+        let user = match &self.clients[&client_id].user {
+            Some(user) => user.name.clone(),
+            None => {
+                return Some(PixelBudget {
+                    remaining: 0,
+                    total: cooldown.pixels,
+                    refill_in: 0.0,
+                })
+            }
+        };
+        let budget = self.budget(canvas_name, user, cooldown);
+        Some(PixelBudget {
+            remaining: budget.remaining,
+            total: cooldown.pixels,
+            refill_in: budget.refill_at.map_or(0.0, |at| {
+                at.saturating_duration_since(std::time::Instant::now())
+                    .as_secs_f64()
+            }),
+        })
+    }
+    fn spend_pixels(
+        &mut self,
+        canvas_name: &str,
+        client_id: ClientId,
+        pixels: usize,
+    ) -> Result<(), String> {
+        let cooldown = match self.canvases[canvas_name].cooldown() {
+            Some(cooldown) => cooldown,
+            None => return Ok(()),
+        };
+        let user = match &self.clients[&client_id].user {
+            Some(user) => user.name.clone(),
+            None => return Err("Log in to draw on a canvas with a cooldown".to_owned()),
+        };
+        let budget = self.budget(canvas_name, user, cooldown);
+        if pixels > budget.remaining {
+            return Err(format!(
+                "Only {} pixels left until the next refill",
+                budget.remaining,
+            ));
+        }
+        budget.remaining -= pixels;
+        if budget.refill_at.is_none() {
+            budget.refill_at = Some(
+                std::time::Instant::now() + std::time::Duration::from_secs_f64(cooldown.period),
+            );
+        }
+        Ok(())
+    }
+    // New canvases take disk space, so only clients that can draw create them, up to a limit
+    fn check_new_canvas(&self, client_id: ClientId, canvas: &str) -> Result<(), String> {
+        if canvas == DEFAULT_CANVAS
+            || self.canvases.contains_key(canvas)
+            || canvas_path(canvas).is_dir()
+        {
+            return Ok(());
+        }
+        if !self.can_edit(client_id) {
+            return Err(format!(
+                "Canvas {:?} does not exist, log in to create it",
+                canvas
+            ));
+        }
+        let count = std::fs::read_dir(SAVE_DIR).map_or(0, |entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter(|entry| entry.file_name().to_str().map_or(false, is_valid_name))
+                .count()
+        });
+        if count >= self.limits.max_canvases {
+            return Err(format!(
+                "Can not create canvas {:?}, there are too many canvases",
+                canvas,
+            ));
+        }
+        Ok(())
+    }
+    fn join(&mut self, client_id: ClientId, canvas: String) {
+        if !is_valid_name(&canvas) {
+            self.send(
+                client_id,
+                ServerMessage::Error(format!("Invalid canvas name {:?}", canvas)),
+            );
+            return;
+        }
+        // Opening a canvas reads from disk while every canvas waits
+        let client = self.clients.get_mut(&client_id).unwrap();
+        if let Err(reason) = client.limiter.check_request(&self.limits) {
+            warn!("Rejected join from client #{}: {}", client_id, reason);
+            self.send(client_id, ServerMessage::Error(reason));
+            self.violation(client_id);
+            return;
+        }
+        if let Err(reason) = self.check_new_canvas(client_id, &canvas) {
+            self.send(client_id, ServerMessage::Error(reason));
+            return;
+        }
+        self.leave(client_id);
+        if !self.canvases.contains_key(&canvas) {
+            let log_index = self.log_indexes.remove(&canvas);
+            match Canvas::new(&canvas, log_index) {
+                Ok(opened) => {
+                    self.canvases.insert(canvas.clone(), opened);
+                }
+                Err(e) => {
+                    error!("Failed to open canvas {:?}: {}", canvas, e);
+                    self.send(
+                        client_id,
+                        ServerMessage::Error(format!("Failed to open canvas {:?}", canvas)),
+                    );
+                    return;
+                }
+            }
+        }
+        info!("Client #{} joined canvas {:?}", client_id, canvas);
+        self.canvases
+            .get_mut(&canvas)
+            .unwrap()
+            .clients
+            .insert(client_id);
+        let regions = self.canvases[&canvas].regions.read().clone();
+        self.send(client_id, ServerMessage::Regions(regions));
+        let layers = self.canvases[&canvas].layer_names();
+        self.send(client_id, ServerMessage::Layers(layers));
+        let stamps = self.canvases[&canvas].stamps.names();
+        self.send(client_id, ServerMessage::Stamps(stamps));
+        let templates = self.canvases[&canvas].templates.list();
+        self.send(client_id, ServerMessage::Templates(templates));
+        let bookmarks = self.canvases[&canvas].bookmarks.read().clone();
+        self.send(client_id, ServerMessage::Bookmarks(bookmarks));
+        let budget = self.pixel_budget(&canvas, client_id);
+        self.send(client_id, ServerMessage::PixelBudget(budget));
+        let client = self.clients.get_mut(&client_id).unwrap();
+        client.canvas = Some(canvas);
+        client.stamps.clear();
+    }
     fn handle_message(&mut self, client_id: ClientId, message: ClientMessage) {
+        if self.kicked.contains(&client_id) {
+            return;
+        }
+        let message = match message {
+            ClientMessage::Authenticate { token } => return self.authenticate(client_id, &token),
+            ClientMessage::Join { canvas } => return self.join(client_id, canvas),
+            message => message,
+        };
+        let canvas_name = match &self.clients[&client_id].canvas {
+            Some(name) => name.clone(),
+            None => {
+                warn!(
+                    "Client #{} sent a message before joining a canvas",
+                    client_id
+                );
+                return;
+            }
+        };
+        // Stamps are expanded here so they are limited, filtered and logged like any other update
+        let mut placed_stamp = None;
+        let message = match message {
+            ClientMessage::PlaceStamp {
+                id,
+                layer,
+                name,
+                position,
+            } => {
+                let canvas = self.canvases.get_mut(&canvas_name).unwrap();
+                match canvas.stamps.get(&name) {
+                    Some(image) => {
+                        let update = stamp_update(layer, image, position);
+                        placed_stamp = Some((name, position));
+                        ClientMessage::Update { id, update }
+                    }
+                    None => {
+                        let reason = format!("Unknown stamp {:?}", name);
+                        self.reject_update(client_id, id, reason);
+                        return;
+                    }
+                }
+            }
+            message => message,
+        };
+        if !self.check_limits(client_id, &message) {
+            return;
+        }
+        let can_edit = self.can_edit(client_id);
+        let user = self.clients[&client_id].user.clone();
+        let author = user.as_ref().map(|user| user.name.clone());
+        let is_admin = user
+            .as_ref()
+            .map_or(false, |user| user.is_in_group(ADMIN_GROUP));
+        let canvas = self.canvases.get_mut(&canvas_name).unwrap();
         match message {
-            ClientMessage::Download { area } => {
-                self.clients
-                    .get_mut(&client_id)
-                    .unwrap()
-                    .send(ServerMessage::Download {
+            ClientMessage::Authenticate { .. }
+            | ClientMessage::Join { .. }
+            | ClientMessage::PlaceStamp { .. } => unreachable!(),
+            ClientMessage::Download { layer, area } => {
+                let message = match canvas.layer(&layer) {
+                    Some(state) => ServerMessage::Download {
                         position: area.bottom_left(),
-                        data: self.state.get(area),
-                    });
+                        data: state.get(area),
+                        layer,
+                    },
+                    None => ServerMessage::DownloadRejected {
+                        reason: format!("Unknown layer {:?}", layer),
+                    },
+                };
+                self.send(client_id, message);
+            }
+            ClientMessage::AddLayer { name } => {
+                let error = if !can_edit {
+                    Some("Log in to add layers".to_owned())
+                } else if !is_valid_name(&name) || canvas.has_layer(&name) {
+                    Some(format!("Can not add layer {:?}", name))
+                } else if canvas.layer_names().len() >= MAX_LAYERS {
+                    Some(format!("Can not have more than {} layers", MAX_LAYERS))
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    self.send(client_id, ServerMessage::Error(error));
+                    return;
+                }
+                canvas.layer_order.write().push(name);
+                let layers = canvas.layer_names();
+                self.broadcast(&canvas_name, ServerMessage::Layers(layers));
             }
             ClientMessage::Update { id, update } => {
-                for (&other_client_id, client) in &mut self.clients {
-                    client.send(ServerMessage::Update {
-                        your_id: if other_client_id == client_id {
-                            Some(id)
-                        } else {
-                            None
+                if !can_edit {
+                    self.reject_update(client_id, id, "Log in to draw".to_owned());
+                    return;
+                }
+                if !canvas.has_layer(update.layer()) {
+                    let reason = format!("Unknown layer {:?}", update.layer());
+                    self.reject_update(client_id, id, reason);
+                    return;
+                }
+                let (update, dropped) = canvas.drop_protected(update, user.as_ref());
+                let spent = self.spend_pixels(&canvas_name, client_id, update.pixel_count());
+                let budget = self.pixel_budget(&canvas_name, client_id);
+                if budget.is_some() {
+                    self.send(client_id, ServerMessage::PixelBudget(budget));
+                }
+                if let Err(reason) = spent {
+                    self.reject_update(client_id, id, reason);
+                    return;
+                }
+                let canvas = self.canvases.get_mut(&canvas_name).unwrap();
+                // Stamps cut by protected regions are sent as pixels
+                let placed_stamp =
+                    placed_stamp
+                        .filter(|_| !dropped)
+                        .and_then(|(name, position)| {
+                            let image = canvas.stamps.get(&name)?.clone();
+                            Some((name, position, image))
+                        });
+                for &other_client_id in &canvas.clients {
+                    let other_client = self.clients.get_mut(&other_client_id).unwrap();
+                    let your_id = (other_client_id == client_id).then_some(id);
+                    let message = match &placed_stamp {
+                        Some((name, position, image)) => {
+                            if other_client.stamps.insert(name.clone()) {
+                                other_client.sender.send(ServerMessage::Stamp {
+                                    name: name.clone(),
+                                    image: image.clone(),
+                                });
+                            }
+                            ServerMessage::PlaceStamp {
+                                your_id,
+                                author: author.clone(),
+                                layer: update.layer().to_owned(),
+                                name: name.clone(),
+                                position: *position,
+                            }
+                        }
+                        None => ServerMessage::Update {
+                            your_id,
+                            author: author.clone(),
+                            update: update.clone(), // TODO: not clone
                         },
-                        update: update.clone(), // TODO: not clone
+                    };
+                    other_client.sender.send(message);
+                }
+                let author = author.as_deref().unwrap_or(ANONYMOUS);
+                let time = unix_time_millis();
+                canvas.log.append(&oplog::Entry {
+                    time,
+                    client_id,
+                    author: author.to_owned(),
+                    canvas: canvas_name.clone(),
+                    update: update.clone(), // TODO: not clone
+                });
+                let layer = update.layer().to_owned();
+                canvas
+                    .layer(&layer)
+                    .unwrap()
+                    .update(update, author, time / 1000);
+                self.end_preview(&canvas_name, client_id);
+                if dropped {
+                    self.send(
+                        client_id,
+                        ServerMessage::Error("Can not draw in a protected region".to_owned()),
+                    );
+                }
+            }
+            ClientMessage::PreviewEnd => self.end_preview(&canvas_name, client_id),
+            ClientMessage::Ping => self.send(client_id, ServerMessage::Pong),
+            ClientMessage::Preview(update) => {
+                if !can_edit {
+                    return;
+                }
+                let (update, _) = canvas.drop_protected(update, user.as_ref());
+                canvas.previewing.insert(client_id);
+                for &other_client_id in &canvas.clients {
+                    if other_client_id != client_id {
+                        self.clients.get_mut(&other_client_id).unwrap().sender.send(
+                            ServerMessage::Preview {
+                                client_id,
+                                update: update.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+            ClientMessage::Attribution { layer, position } => {
+                let now = unix_time_millis() / 1000;
+                let attribution = canvas
+                    .layer(&layer)
+                    .and_then(|state| state.attribution(position))
+                    .map(|(author, time)| Attribution {
+                        author,
+                        time,
+                        age: now.saturating_sub(time) as f64,
                     });
+                self.send(
+                    client_id,
+                    ServerMessage::Attribution {
+                        position,
+                        attribution,
+                    },
+                );
+            }
+            ClientMessage::AddStamp { name, image } => {
+                let size = image.size();
+                let error = if !can_edit {
+                    Some("Log in to add stamps".to_owned())
+                } else if image.as_slice().len() != size.x * size.y || size.x == 0 || size.y == 0 {
+                    Some("Invalid stamp image".to_owned())
+                } else if size.x * size.y > self.limits.max_update_pixels {
+                    Some(format!(
+                        "Stamp is too big ({} > {} pixels)",
+                        size.x * size.y,
+                        self.limits.max_update_pixels,
+                    ))
+                } else {
+                    canvas.stamps.add(&name, image).err()
+                };
+                if let Some(error) = error {
+                    self.send(client_id, ServerMessage::Error(error));
+                    return;
+                }
+                // The uploader does not keep the image, it downloads it like everyone else
+                info!("Stamp {:?} added to canvas {:?}", name, canvas_name);
+                let stamps = canvas.stamps.names();
+                self.broadcast(&canvas_name, ServerMessage::Stamps(stamps));
+            }
+            ClientMessage::DownloadStamp { name } => {
+                let message = match canvas.stamps.get(&name) {
+                    Some(image) => {
+                        let client = self.clients.get_mut(&client_id).unwrap();
+                        client.stamps.insert(name.clone());
+                        ServerMessage::Stamp {
+                            name,
+                            image: image.clone(),
+                        }
+                    }
+                    None => ServerMessage::Error(format!("Unknown stamp {:?}", name)),
+                };
+                self.send(client_id, message);
+            }
+            ClientMessage::DownloadTemplate { name } => {
+                let message = match canvas.templates.get(&name) {
+                    Some((info, image)) => ServerMessage::Template { info, image },
+                    None => ServerMessage::Error(format!("Unknown template {:?}", name)),
+                };
+                self.send(client_id, message);
+            }
+            ClientMessage::Overview { center } => {
+                let (area, data) = canvas.overview(center);
+                self.send(client_id, ServerMessage::Overview { area, data });
+            }
+            ClientMessage::Cursor(position) => {
+                for &other_client_id in &canvas.clients {
+                    if other_client_id != client_id {
+                        self.clients.get_mut(&other_client_id).unwrap().sender.send(
+                            ServerMessage::Cursor {
+                                client_id,
+                                author: author.clone(),
+                                position: Some(position),
+                            },
+                        );
+                    }
+                }
+            }
+            ClientMessage::AddBookmark(_) | ClientMessage::RemoveBookmark(_) if !can_edit => {
+                self.send(
+                    client_id,
+                    ServerMessage::Error("Log in to change bookmarks".to_owned()),
+                );
+            }
+            ClientMessage::AddBookmark(bookmark) => {
+                let error = if !is_valid_name(&bookmark.name)
+                    || !bookmark.center.x.is_finite()
+                    || !bookmark.center.y.is_finite()
+                    || !bookmark.fov.is_finite()
+                {
+                    Some(format!("Invalid bookmark {:?}", bookmark.name))
+                } else if canvas.bookmarks.read().len() >= MAX_BOOKMARKS {
+                    Some(format!(
+                        "Can not have more than {} bookmarks",
+                        MAX_BOOKMARKS
+                    ))
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    self.send(client_id, ServerMessage::Error(error));
+                    return;
+                }
+                {
+                    let mut bookmarks = canvas.bookmarks.write();
+                    bookmarks.retain(|other| other.name != bookmark.name);
+                    bookmarks.push(bookmark);
+                }
+                let bookmarks = canvas.bookmarks.read().clone();
+                self.broadcast(&canvas_name, ServerMessage::Bookmarks(bookmarks));
+            }
+            ClientMessage::RemoveBookmark(name) => {
+                canvas
+                    .bookmarks
+                    .write()
+                    .retain(|bookmark| bookmark.name != name);
+                let bookmarks = canvas.bookmarks.read().clone();
+                self.broadcast(&canvas_name, ServerMessage::Bookmarks(bookmarks));
+            }
+            ClientMessage::HistoryRange => {
+                let range = canvas.log.time_range();
+                self.send(client_id, ServerMessage::HistoryRange(range));
+            }
+            ClientMessage::DownloadHistory { area, time } => {
+                self.history.push(history::Request {
+                    client_id,
+                    checkpoints: canvas.log.checkpoints(time),
+                    log_len: canvas.log.end_offset(time),
+                    layers: canvas.layer_names(),
+                    canvas: canvas_name,
+                    area,
+                    time,
+                });
+            }
+            ClientMessage::AddRegion(_)
+            | ClientMessage::RemoveRegion(_)
+            | ClientMessage::SetCooldown(_)
+                if !is_admin =>
+            {
+                self.send(
+                    client_id,
+                    ServerMessage::Error("Only admins can change canvas settings".to_owned()),
+                );
+            }
+            ClientMessage::AddRegion(region) => {
+                canvas.regions.write().push(region);
+                let regions = canvas.regions.read().clone();
+                self.broadcast(&canvas_name, ServerMessage::Regions(regions));
+            }
+            ClientMessage::RemoveRegion(area) => {
+                canvas.regions.write().retain(|region| region.area != area);
+                let regions = canvas.regions.read().clone();
+                self.broadcast(&canvas_name, ServerMessage::Regions(regions));
+            }
+            ClientMessage::SetCooldown(cooldown) => {
+                if let Some(Err(error)) = cooldown.map(|cooldown| cooldown.validate()) {
+                    self.send(client_id, ServerMessage::Error(error));
+                    return;
+                }
+                info!("Canvas {:?} cooldown set to {:?}", canvas_name, cooldown);
+                canvas.settings.write().cooldown = cooldown;
+                self.budgets.retain(|(name, _), _| *name != canvas_name);
+                let clients: Vec<ClientId> = canvas.clients.iter().copied().collect();
+                for other_client_id in clients {
+                    let budget = self.pixel_budget(&canvas_name, other_client_id);
+                    self.send(other_client_id, ServerMessage::PixelBudget(budget));
                 }
-                self.state.update(update);
             }
         }
     }
+    fn end_preview(&mut self, canvas_name: &str, client_id: ClientId) {
+        let canvas = self.canvases.get_mut(canvas_name).unwrap();
+        if !canvas.previewing.remove(&client_id) {
+            return;
+        }
+        for &other_client_id in &canvas.clients {
+            if other_client_id != client_id {
+                self.clients
+                    .get_mut(&other_client_id)
+                    .unwrap()
+                    .sender
+                    .send(ServerMessage::PreviewEnd { client_id });
+            }
+        }
+    }
+    fn leave(&mut self, client_id: ClientId) {
+        let canvas_name = match self.clients.get_mut(&client_id).unwrap().canvas.take() {
+            Some(name) => name,
+            None => return,
+        };
+        self.end_preview(&canvas_name, client_id);
+        self.history.forget(client_id);
+        let canvas = self.canvases.get_mut(&canvas_name).unwrap();
+        canvas.clients.remove(&client_id);
+        for &other_client_id in &canvas.clients {
+            self.clients
+                .get_mut(&other_client_id)
+                .unwrap()
+                .sender
+                .send(ServerMessage::Cursor {
+                    client_id,
+                    author: None,
+                    position: None,
+                });
+        }
+        if canvas.clients.is_empty() {
+            info!("Closing canvas {:?}", canvas_name);
+            let canvas = self.canvases.remove(&canvas_name).unwrap();
+            self.log_indexes
+                .insert(canvas_name, canvas.log.index().clone());
+        }
+    }
+    fn disconnect(&mut self, client_id: ClientId) {
+        if self.kicked.remove(&client_id) {
+            return;
+        }
+        self.leave(client_id);
+        self.clients.remove(&client_id);
+    }
 }
 
 pub struct Server {
@@ -52,10 +926,11 @@ pub struct Server {
 }
 
 impl Server {
-    pub fn new() -> Self {
-        Self {
-            state: Arc::new(Mutex::new(ServerState::new())),
-        }
+    pub fn new(opt: &Opt) -> Self {
+        let state = Arc::new_cyclic(|state| {
+            Mutex::new(ServerState::new(opt, history::Workers::new(state.clone())))
+        });
+        Self { state }
     }
 }
 
@@ -72,7 +947,7 @@ impl geng::net::Receiver<ClientMessage> for ClientConnection {
 
 impl Drop for ClientConnection {
     fn drop(&mut self) {
-        self.state.lock().unwrap().clients.remove(&self.id);
+        self.state.lock().unwrap().disconnect(self.id);
     }
 }
 
@@ -87,7 +962,21 @@ impl geng::net::server::App for Server {
         let mut state = self.state.lock().unwrap();
         let id = state.next_client_id;
         state.next_client_id += 1;
-        state.clients.insert(id, sender);
+        let limiter = limits::RateLimiter::new(&state.limits);
+        sender.send(ServerMessage::UpdateLimits {
+            max_pixels: state.limits.max_update_pixels,
+            pixels_per_second: state.limits.pixels_per_second,
+        });
+        state.clients.insert(
+            id,
+            ClientState {
+                sender,
+                user: None,
+                canvas: None,
+                limiter,
+                stamps: default(),
+            },
+        );
         ClientConnection {
             id,
             state: self.state.clone(),
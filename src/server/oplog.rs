@@ -0,0 +1,418 @@
+use super::*;
+
+use std::io::{BufRead, Read, Write};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub time: u64, // Unix time in milliseconds
+    pub client_id: ClientId,
+    pub author: String,
+    pub canvas: String,
+    pub update: Update,
+}
+
+// Logs start with these bytes and a version, logs without them are from before layers
+const MAGIC: [u8; 4] = *b"olog";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8;
+
+// Entries from before layers, everything was drawn on the base layer
+#[derive(Deserialize)]
+enum LegacyUpdate {
+    Draw(Vec<Pixel>),
+}
+
+#[derive(Deserialize)]
+struct LegacyEntry {
+    time: u64,
+    client_id: ClientId,
+    author: String,
+    canvas: String,
+    update: LegacyUpdate,
+}
+
+impl From<LegacyEntry> for Entry {
+    fn from(entry: LegacyEntry) -> Self {
+        let update = match entry.update {
+            LegacyUpdate::Draw(pixels) => Update::Draw {
+                layer: BASE_LAYER.to_owned(),
+                pixels,
+            },
+        };
+        Self {
+            time: entry.time,
+            client_id: entry.client_id,
+            author: entry.author,
+            canvas: entry.canvas,
+            update,
+        }
+    }
+}
+
+fn write_header(writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())
+}
+
+// 0 for logs without a header
+fn read_version(reader: &mut impl BufRead) -> std::io::Result<u32> {
+    if !reader.fill_buf()?.starts_with(&MAGIC) {
+        return Ok(0);
+    }
+    reader.consume(MAGIC.len());
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    Ok(u32::from_le_bytes(version))
+}
+
+// Logs from before layers are rewritten with a header, so new entries can be appended to them
+fn upgrade(path: &std::path::Path) {
+    let mut reader = match std::fs::File::open(path) {
+        Ok(file) => std::io::BufReader::new(file),
+        Err(_) => return,
+    };
+    match reader.fill_buf() {
+        Ok(data) if data.is_empty() => return,
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Failed to read log {:?}: {}", path, e);
+            return;
+        }
+    }
+    if read_version(&mut reader).map_or(true, |version| version != 0) {
+        return;
+    }
+    info!("Upgrading log {:?}", path);
+    let upgraded = path.with_extension("upgraded");
+    let file = std::fs::File::create(&upgraded).expect("Failed to create upgraded log");
+    let mut writer = std::io::BufWriter::new(file);
+    write_header(&mut writer).expect("Failed to write upgraded log");
+    for entry in read(path) {
+        bincode::serialize_into(&mut writer, &entry).expect("Failed to write upgraded log");
+    }
+    let file = writer.into_inner().expect("Failed to write upgraded log");
+    file.sync_all().expect("Failed to sync upgraded log");
+    std::fs::rename(&upgraded, path).expect("Failed to replace log");
+}
+
+pub fn path(canvas: &str) -> std::path::PathBuf {
+    canvas_path(canvas).join("log")
+}
+
+// Syncing after every entry is slow, so the writer syncs at most this often
+const SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+// The offset of every this many entries is remembered,
+// so history snapshots only read the part of the log they need
+const CHECKPOINT_INTERVAL: usize = 1024;
+
+// Where the entries of a log are, the server keeps it after closing a canvas
+// so reopening the canvas does not read the whole log again
+#[derive(Debug, Clone)]
+pub struct Index {
+    len: u64,
+    entries: usize,
+    range: Option<(u64, u64)>,
+    // Time and offset of every CHECKPOINT_INTERVAL-th entry
+    checkpoints: Vec<(u64, u64)>,
+}
+
+impl Index {
+    fn new() -> Self {
+        Self {
+            len: HEADER_LEN,
+            entries: 0,
+            range: None,
+            checkpoints: Vec::new(),
+        }
+    }
+    fn read(path: &std::path::Path) -> Self {
+        let mut index = Self::new();
+        for entry in read(path) {
+            let size = bincode::serialized_size(&entry).expect("Failed to measure log entry");
+            index.add(entry.time, size);
+        }
+        index
+    }
+    fn add(&mut self, time: u64, size: u64) {
+        if self.entries % CHECKPOINT_INTERVAL == 0 {
+            self.checkpoints.push((time, self.len));
+        }
+        self.entries += 1;
+        self.len += size;
+        let start = self.range.map_or(time, |(start, _)| start);
+        self.range = Some((start, time));
+    }
+}
+
+// Entries are written by a background thread, dropping the log waits for it to finish
+pub struct Log {
+    sender: Option<std::sync::mpsc::Sender<Vec<u8>>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    index: Index,
+    // Length of the synced part of the log, entries after it may still be in the queue
+    flushed: Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        self.sender.take();
+        self.thread.take().unwrap().join().unwrap();
+    }
+}
+
+impl Log {
+    // A cached index is only used if the log still has the same length
+    pub fn open(path: impl AsRef<std::path::Path>, cached: Option<Index>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        upgrade(&path);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        if file.metadata()?.len() == 0 {
+            write_header(&mut file)?;
+        }
+        let file_len = file.metadata()?.len();
+        let index = match cached {
+            Some(index) if index.len == file_len => index,
+            _ => Index::read(&path),
+        };
+        // A tail left by a crash mid-write would hide every entry appended after it
+        if file_len > index.len {
+            warn!(
+                "Log {:?} is truncated or corrupted, dropping its last {} bytes",
+                path,
+                file_len - index.len,
+            );
+            file.set_len(index.len)?;
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let flushed = Arc::new(std::sync::atomic::AtomicU64::new(index.len));
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(std::thread::spawn({
+                let flushed = flushed.clone();
+                move || writer(path, file, flushed, receiver)
+            })),
+            index,
+            flushed,
+        })
+    }
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+    pub fn time_range(&self) -> Option<(u64, u64)> {
+        self.index.range
+    }
+    fn flushed(&self) -> u64 {
+        self.flushed.load(std::sync::atomic::Ordering::SeqCst)
+    }
+    // Every entry up to the time is in this many bytes at the start of the log,
+    // unless it is not written yet
+    pub fn end_offset(&self, time: u64) -> u64 {
+        self.index
+            .checkpoints
+            .iter()
+            .find(|&&(checkpoint_time, _)| checkpoint_time > time)
+            .map_or(self.index.len, |&(_, offset)| offset)
+            .min(self.flushed())
+    }
+    // Offsets of the written checkpoints that only have entries up to the time before them
+    pub fn checkpoints(&self, time: u64) -> Vec<u64> {
+        let flushed = self.flushed();
+        self.index
+            .checkpoints
+            .iter()
+            .take_while(|&&(checkpoint_time, offset)| checkpoint_time <= time && offset <= flushed)
+            .map(|&(_, offset)| offset)
+            .collect()
+    }
+    // Every entry is written with a single call, so a crash can only lose the tail
+    pub fn append(&mut self, entry: &Entry) {
+        let data = bincode::serialize(entry).expect("Failed to serialize log entry");
+        self.index.add(entry.time, data.len() as u64);
+        if self.sender.as_ref().unwrap().send(data).is_err() {
+            error!("Log writer is gone, dropping entry at {}", entry.time);
+        }
+    }
+}
+
+// A failed write is cut off and nothing more is written,
+// so the entries already in the log stay readable
+fn writer(
+    path: std::path::PathBuf,
+    mut file: std::fs::File,
+    flushed: Arc<std::sync::atomic::AtomicU64>,
+    receiver: std::sync::mpsc::Receiver<Vec<u8>>,
+) {
+    let mut len = flushed.load(std::sync::atomic::Ordering::SeqCst);
+    while let Ok(data) = receiver.recv() {
+        for data in std::iter::once(data).chain(receiver.try_iter()) {
+            if let Err(e) = file.write_all(&data) {
+                error!("Failed to write log {:?}, not logging anymore: {}", path, e);
+                if let Err(e) = file.set_len(len) {
+                    error!("Failed to cut off the partial entry of {:?}: {}", path, e);
+                }
+                return;
+            }
+            len += data.len() as u64;
+        }
+        match file.sync_data() {
+            Ok(()) => flushed.store(len, std::sync::atomic::Ordering::SeqCst),
+            Err(e) => error!("Failed to sync log {:?}: {}", path, e),
+        }
+        std::thread::sleep(SYNC_INTERVAL);
+    }
+}
+
+pub fn read(path: impl AsRef<std::path::Path>) -> impl Iterator<Item = Entry> {
+    read_range(path, 0, u64::MAX)
+}
+
+// Only reads the entries between the offsets, start has to be where an entry starts
+fn read_range(
+    path: impl AsRef<std::path::Path>,
+    start: u64,
+    end: u64,
+) -> impl Iterator<Item = Entry> {
+    let path = path.as_ref().to_owned();
+    let open = || -> std::io::Result<_> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(&path)?);
+        let version = read_version(&mut reader)?;
+        let position = if version == 0 { 0 } else { HEADER_LEN };
+        let start = start.max(position);
+        reader.seek_relative((start - position) as i64)?;
+        Ok((reader.take(end.saturating_sub(start)), version))
+    };
+    let mut reader = match open() {
+        Ok((_, version)) if version > VERSION => {
+            warn!("Log {:?} has unknown version {}", path, version);
+            None
+        }
+        Ok(reader) => Some(reader),
+        Err(e) => {
+            warn!("Failed to read log {:?}: {}", path, e);
+            None
+        }
+    };
+    std::iter::from_fn(move || {
+        let (reader, version) = reader.as_mut()?;
+        if reader.fill_buf().expect("Failed to read log").is_empty() {
+            return None;
+        }
+        let entry = match *version {
+            0 => bincode::deserialize_from::<_, LegacyEntry>(reader).map(Entry::from),
+            _ => bincode::deserialize_from(reader),
+        };
+        match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Log {:?} is truncated or corrupted: {}", path, e);
+                None
+            }
+        }
+    })
+}
+
+// Pixels of every layer inside an area
+pub type AreaState = HashMap<String, Matrix<Rgba<u8>>>;
+
+// Applies the entries between the offsets up to the time
+pub fn replay_area(
+    canvas: &str,
+    state: &mut AreaState,
+    area: AABB<i32>,
+    start: u64,
+    end: u64,
+    time: u64,
+) {
+    let empty = Matrix::filled_with(area.size().map(|x| x as usize), Rgba::TRANSPARENT_BLACK);
+    for entry in read_range(path(canvas), start, end).take_while(|entry| entry.time <= time) {
+        match entry.update {
+            Update::Draw { layer, pixels } => {
+                let data = state.entry(layer).or_insert_with(|| empty.clone());
+                for pixel in pixels {
+                    if area.contains(pixel.position) {
+                        data[(pixel.position - area.bottom_left()).map(|x| x as usize)] =
+                            pixel.color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn compose(state: &AreaState, layers: &[String], area: AABB<i32>) -> Matrix<Rgba<u8>> {
+    let mut result = Matrix::filled_with(area.size().map(|x| x as usize), Rgba::TRANSPARENT_BLACK);
+    for layer in layers {
+        if let Some(data) = state.get(layer) {
+            blend_layer(&mut result, data);
+        }
+    }
+    result
+}
+
+// Returns the names of the layers that were replayed
+fn replay(canvas: &str, target: &std::path::Path) -> Vec<String> {
+    let mut layers = HashMap::<String, texture::Infinite>::new();
+    let mut count = 0;
+    for entry in read(path(canvas)) {
+        let layer = entry.update.layer().to_owned();
+        layers
+            .entry(layer)
+            .or_insert_with_key(|layer| texture::Infinite::new(layer_path(target, layer)))
+            .update(entry.update, &entry.author, entry.time / 1000);
+        count += 1;
+    }
+    info!("Replayed {} updates", count);
+    layers.into_keys().collect()
+}
+
+pub fn rebuild(canvas: &str) {
+    let target = canvas_path(canvas).with_extension("rebuilt");
+    if target.exists() {
+        panic!("{:?} already exists", target);
+    }
+    info!("Rebuilding canvas {:?} into {:?}", canvas, target);
+    replay(canvas, &target);
+    let layer_order = layer_order_path(canvas);
+    if layer_order.is_file() {
+        std::fs::copy(&layer_order, target.join("layer_order")).expect("Failed to copy layers");
+    }
+}
+
+pub fn verify(canvas: &str) -> bool {
+    let target = canvas_path(canvas).with_extension("verify");
+    if target.exists() {
+        std::fs::remove_dir_all(&target).expect("Failed to clean up");
+    }
+    let mut layers = replay(canvas, &target);
+    layers.extend(saved_layer_names(canvas));
+    layers.sort();
+    layers.dedup();
+    let mut ok = true;
+    for layer in layers {
+        let mut saved = texture::Infinite::new(layer_path(&canvas_path(canvas), &layer));
+        let mut replayed = texture::Infinite::new(layer_path(&target, &layer));
+        let chunks: HashSet<Vec2<i32>> = saved
+            .saved_chunks()
+            .into_iter()
+            .chain(replayed.saved_chunks())
+            .collect();
+        for chunk_pos in chunks {
+            if saved.chunk_pixels(chunk_pos) != replayed.chunk_pixels(chunk_pos) {
+                error!(
+                    "Chunk {:?} of layer {:?} does not match the log",
+                    chunk_pos, layer,
+                );
+                ok = false;
+            }
+        }
+    }
+    std::fs::remove_dir_all(&target).expect("Failed to clean up");
+    if ok {
+        info!("Canvas {:?} matches the log", canvas);
+    }
+    ok
+}
@@ -0,0 +1,90 @@
+use super::*;
+
+const MAX_STAMPS: usize = 256;
+
+fn to_image(data: &Matrix<Rgba<u8>>) -> image::RgbaImage {
+    let size = data.size();
+    image::RgbaImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        let color = data[vec2(x as usize, size.y - 1 - y as usize)];
+        image::Rgba([color.r, color.g, color.b, color.a])
+    })
+}
+
+pub fn load(path: &std::path::Path) -> image::ImageResult<Matrix<Rgba<u8>>> {
+    Ok(matrix_from_image(&image::open(path)?.to_rgba8()))
+}
+
+// Stamps are saved as PNG files, so they can also be added by copying images into the dir
+pub struct Stamps {
+    path: std::path::PathBuf,
+    images: HashMap<String, Matrix<Rgba<u8>>>,
+}
+
+impl Stamps {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            images: default(),
+        }
+    }
+    fn file(&self, name: &str) -> std::path::PathBuf {
+        self.path.join(format!("{}.png", name))
+    }
+    pub fn names(&self) -> Vec<String> {
+        let entries = match std::fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()?.to_str()? != "png" {
+                    return None;
+                }
+                let name = path.file_stem()?.to_str()?.to_owned();
+                is_valid_name(&name).then_some(name)
+            })
+            .collect();
+        names.sort();
+        names
+    }
+    pub fn get(&mut self, name: &str) -> Option<&Matrix<Rgba<u8>>> {
+        if !is_valid_name(name) {
+            return None;
+        }
+        if !self.images.contains_key(name) {
+            let file = self.file(name);
+            if !file.exists() {
+                return None;
+            }
+            let image = match load(&file) {
+                Ok(image) => image,
+                Err(e) => {
+                    warn!("Failed to load stamp {:?}: {}", file, e);
+                    return None;
+                }
+            };
+            self.images.insert(name.to_owned(), image);
+        }
+        self.images.get(name)
+    }
+    pub fn add(&mut self, name: &str, image: Matrix<Rgba<u8>>) -> Result<(), String> {
+        if !is_valid_name(name) {
+            return Err(format!("Invalid stamp name {:?}", name));
+        }
+        let file = self.file(name);
+        // Placed stamps are sent by name, so an image never changes once added
+        if file.exists() {
+            return Err(format!("Stamp {:?} already exists", name));
+        }
+        if self.names().len() >= MAX_STAMPS {
+            return Err(format!("Can not have more than {} stamps", MAX_STAMPS));
+        }
+        std::fs::create_dir_all(&self.path).expect("Failed to create stamps dir");
+        to_image(&image)
+            .save(&file)
+            .map_err(|e| format!("Failed to save stamp: {}", e))?;
+        self.images.insert(name.to_owned(), image);
+        Ok(())
+    }
+}
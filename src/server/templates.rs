@@ -0,0 +1,60 @@
+use super::*;
+
+// Every non-empty line of `list.txt` in the templates dir is `<name> <x> <y>`, lines starting with # are ignored.
+// The image is `<name>.png` next to it, placed with its bottom left corner at x, y.
+pub struct Templates {
+    path: std::path::PathBuf,
+}
+
+impl Templates {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+    pub fn list(&self) -> Vec<TemplateInfo> {
+        let contents = match std::fs::read_to_string(self.path.join("list.txt")) {
+            Ok(contents) => contents,
+            Err(_) => return Vec::new(),
+        };
+        let mut result = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let info = match parts.as_slice() {
+                &[name, x, y] if is_valid_name(name) => {
+                    match (x.parse::<i32>(), y.parse::<i32>()) {
+                        (Ok(x), Ok(y)) => TemplateInfo {
+                            name: name.to_owned(),
+                            position: vec2(x, y),
+                        },
+                        _ => {
+                            warn!("Invalid template position in {:?}", line);
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    warn!("Invalid template line {:?}", line);
+                    continue;
+                }
+            };
+            result.push(info);
+        }
+        result
+    }
+    pub fn get(&self, name: &str) -> Option<(TemplateInfo, Matrix<Rgba<u8>>)> {
+        let info = self.list().into_iter().find(|info| info.name == name)?;
+        let file = self.path.join(format!("{}.png", name));
+        match stamps::load(&file) {
+            Ok(image) => Some((info, image)),
+            Err(e) => {
+                warn!("Failed to load template {:?}: {}", file, e);
+                None
+            }
+        }
+    }
+}
@@ -1,8 +1,71 @@
 use super::*;
 
+pub const CHUNK_SIZE: usize = 256;
+
+// Every pixel of an overview is the average of a block this big
+pub const OVERVIEW_SCALE: usize = 32;
+
+fn downscale(pixels: &Matrix<Rgba<u8>>, factor: usize) -> Matrix<Rgba<u8>> {
+    Matrix::from_fn(pixels.size() / factor, |pos| {
+        let mut sum = Rgba::new(0.0, 0.0, 0.0, 0.0);
+        for x in 0..factor {
+            for y in 0..factor {
+                let color: Rgba<f32> = pixels[pos * factor + vec2(x, y)].convert();
+                sum.r += color.r * color.a;
+                sum.g += color.g * color.a;
+                sum.b += color.b * color.a;
+                sum.a += color.a;
+            }
+        }
+        if sum.a == 0.0 {
+            return Rgba::TRANSPARENT_BLACK;
+        }
+        Rgba::new(
+            sum.r / sum.a,
+            sum.g / sum.a,
+            sum.b / sum.a,
+            sum.a / (factor * factor) as f32,
+        )
+        .convert()
+    })
+}
+
+#[derive(Default)]
+struct OverviewCache {
+    // None for chunks that are not saved
+    overviews: HashMap<Vec2<i32>, Option<Matrix<Rgba<u8>>>>,
+    loading: HashSet<Vec2<i32>>,
+}
+
+// Chunks that are not in memory are read by a background thread,
+// their overviews are there for a later request
+fn load_overviews(cache: Arc<Mutex<OverviewCache>>, chunks: Vec<(Vec2<i32>, std::path::PathBuf)>) {
+    for (chunk_pos, path) in chunks {
+        let overview = if path.is_file() {
+            Chunk::read(&path).map(|chunk| Some(downscale(&chunk.pixels, OVERVIEW_SCALE)))
+        } else {
+            Ok(None)
+        };
+        let mut cache = cache.lock().unwrap();
+        // Not loading anymore if it changed in the meantime
+        if !cache.loading.remove(&chunk_pos) {
+            continue;
+        }
+        match overview {
+            Ok(overview) => {
+                cache.overviews.insert(chunk_pos, overview);
+            }
+            // Could be in the middle of being saved, the next request tries again
+            Err(e) => warn!("Failed to read chunk {:?} for an overview: {}", path, e),
+        }
+    }
+}
+
 pub struct Infinite {
     path: std::path::PathBuf,
     chunks: HashMap<Vec2<i32>, AutoSaved<Chunk>>,
+    attributions: HashMap<Vec2<i32>, AutoSaved<ChunkAttribution>>,
+    overviews: Arc<Mutex<OverviewCache>>,
 }
 
 impl Infinite {
@@ -11,21 +74,46 @@ impl Infinite {
         Self {
             path: path.as_ref().to_owned(),
             chunks: default(),
+            attributions: default(),
+            overviews: default(),
         }
     }
-    pub fn update(&mut self, update: Update) {
+    pub fn update(&mut self, update: Update, author: &str, time: u64) {
         match update {
-            Update::Draw(pixels) => {
+            Update::Draw { pixels, .. } => {
+                let cache = self.overviews.clone();
+                let mut cache = cache.lock().unwrap();
                 for pixel in pixels {
                     let chunk_pos = pixel.position.map(|x| div_down(x, Chunk::SIZE as i32));
-                    let mut chunk = self.get_chunk(chunk_pos).write();
                     let in_chunk =
                         (pixel.position - chunk_pos * Chunk::SIZE as i32).map(|x| x as usize);
-                    chunk.pixels[in_chunk] = pixel.color;
+                    self.get_chunk(chunk_pos).write().pixels[in_chunk] = pixel.color;
+                    cache.overviews.remove(&chunk_pos);
+                    cache.loading.remove(&chunk_pos);
+                    let mut attribution = self.get_attribution(chunk_pos).write();
+                    let author_index = attribution.author_index(author);
+                    attribution.pixels[in_chunk] = (author_index, time as u32);
                 }
             }
         }
     }
+    pub fn attribution(&mut self, position: Vec2<i32>) -> Option<(String, u64)> {
+        let chunk_pos = position.map(|x| div_down(x, Chunk::SIZE as i32));
+        let in_chunk = (position - chunk_pos * Chunk::SIZE as i32).map(|x| x as usize);
+        // Looking at a chunk nobody drew on should not create it
+        if !self.attributions.contains_key(&chunk_pos) && !self.attribution_path(chunk_pos).exists()
+        {
+            return None;
+        }
+        let attribution = self.get_attribution(chunk_pos).read();
+        let (author, time) = attribution.pixels[in_chunk];
+        let author = match author {
+            0 => return None,
+            ChunkAttribution::UNKNOWN_AUTHOR => "unknown".to_owned(),
+            author => attribution.authors[author as usize - 1].clone(),
+        };
+        Some((author, time as u64))
+    }
     pub fn get(&mut self, rect: AABB<i32>) -> Matrix<Rgba<u8>> {
         let mut result =
             Matrix::filled_with(rect.size().map(|x| x as usize), Rgba::TRANSPARENT_BLACK);
@@ -58,13 +146,77 @@ impl Infinite {
         }
         result
     }
+    pub fn saved_chunks(&self) -> Vec<Vec2<i32>> {
+        let mut result = Vec::new();
+        let entries = match std::fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(_) => return result,
+        };
+        for entry in entries {
+            let path = entry.expect("Failed to read canvas dir").path();
+            if path.extension().map_or(true, |ext| ext != "chunk") {
+                continue;
+            }
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            if let Some((x, y)) = name.split_once('_') {
+                if let (Ok(x), Ok(y)) = (x.parse(), y.parse()) {
+                    result.push(vec2(x, y));
+                }
+            }
+        }
+        result
+    }
+    pub fn chunk_pixels(&mut self, chunk_pos: Vec2<i32>) -> Matrix<Rgba<u8>> {
+        self.get_chunk(chunk_pos).read().pixels.clone()
+    }
+    // Overviews of the chunks in the area that are ready, without reading chunks from disk
+    pub fn overviews(&self, area: AABB<i32>) -> Vec<(Vec2<i32>, Matrix<Rgba<u8>>)> {
+        let mut result = Vec::new();
+        let mut missing = Vec::new();
+        let mut cache = self.overviews.lock().unwrap();
+        for chunk_x in area.x_min..area.x_max {
+            for chunk_y in area.y_min..area.y_max {
+                let chunk_pos = vec2(chunk_x, chunk_y);
+                if let Some(overview) = cache.overviews.get(&chunk_pos) {
+                    result.extend(overview.clone().map(|overview| (chunk_pos, overview)));
+                    continue;
+                }
+                let chunk = self.chunks.get(&chunk_pos);
+                if let Some(chunk) = chunk.and_then(|chunk| chunk.read_loaded()) {
+                    let overview = downscale(&chunk.pixels, OVERVIEW_SCALE);
+                    cache.overviews.insert(chunk_pos, Some(overview.clone()));
+                    result.push((chunk_pos, overview));
+                } else if cache.loading.insert(chunk_pos) {
+                    missing.push((chunk_pos, self.chunk_path(chunk_pos)));
+                }
+            }
+        }
+        if !missing.is_empty() {
+            let cache = self.overviews.clone();
+            std::thread::spawn(move || load_overviews(cache, missing));
+        }
+        result
+    }
+    fn chunk_path(&self, chunk_pos: Vec2<i32>) -> std::path::PathBuf {
+        self.path
+            .join(format!("{}_{}.chunk", chunk_pos.x, chunk_pos.y))
+    }
     fn get_chunk(&mut self, chunk_pos: Vec2<i32>) -> &mut AutoSaved<Chunk> {
-        self.chunks.entry(chunk_pos).or_insert_with(|| {
-            AutoSaved::new(
-                self.path
-                    .join(format!("{}_{}.chunk", chunk_pos.x, chunk_pos.y)),
-            )
-        })
+        let path = self.chunk_path(chunk_pos);
+        self.chunks
+            .entry(chunk_pos)
+            .or_insert_with(|| AutoSaved::new(path))
+    }
+    // Kept in a separate file so that chunks saved before attribution existed still load
+    fn attribution_path(&self, chunk_pos: Vec2<i32>) -> std::path::PathBuf {
+        self.path
+            .join(format!("{}_{}.attribution", chunk_pos.x, chunk_pos.y))
+    }
+    fn get_attribution(&mut self, chunk_pos: Vec2<i32>) -> &mut AutoSaved<ChunkAttribution> {
+        let path = self.attribution_path(chunk_pos);
+        self.attributions
+            .entry(chunk_pos)
+            .or_insert_with(|| AutoSaved::new(path))
     }
 }
 
@@ -82,5 +234,42 @@ impl Default for Chunk {
 }
 
 impl Chunk {
-    const SIZE: usize = 256;
+    const SIZE: usize = CHUNK_SIZE;
+    // Same format as AutoSaved, for reading without loading the chunk
+    fn read(path: &std::path::Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let reader = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+        bincode::deserialize_from(reader).map_err(|e| e.to_string())
+    }
+}
+
+// Every pixel stores an index into the authors (0 is nobody, UNKNOWN_AUTHOR is used once the
+// authors are full) and the unix time of the change
+#[derive(Serialize, Deserialize)]
+struct ChunkAttribution {
+    authors: Vec<String>,
+    pixels: Matrix<(u16, u32)>,
+}
+
+impl Default for ChunkAttribution {
+    fn default() -> Self {
+        Self {
+            authors: Vec::new(),
+            pixels: Matrix::filled_with(vec2(Chunk::SIZE, Chunk::SIZE), (0, 0)),
+        }
+    }
+}
+
+impl ChunkAttribution {
+    const UNKNOWN_AUTHOR: u16 = u16::MAX;
+    fn author_index(&mut self, author: &str) -> u16 {
+        if let Some(index) = self.authors.iter().position(|a| a == author) {
+            return (index + 1) as u16;
+        }
+        if self.authors.len() + 1 >= Self::UNKNOWN_AUTHOR as usize {
+            return Self::UNKNOWN_AUTHOR;
+        }
+        self.authors.push(author.to_owned());
+        self.authors.len() as u16
+    }
 }
@@ -0,0 +1,115 @@
+use super::*;
+
+pub struct Options {
+    pub area: AABB<i32>,
+    pub step: f64,
+    pub output: std::path::PathBuf,
+    pub gif: bool,
+}
+
+impl Options {
+    pub fn new(opt: &Opt) -> Self {
+        Self {
+            area: opt.timelapse_area,
+            step: opt.timelapse_step,
+            output: opt.timelapse_output.clone(),
+            gif: opt.timelapse_gif,
+        }
+    }
+}
+
+// Transparent pixels are shown on white just like in the client
+fn to_image(data: &Matrix<Rgba<u8>>) -> image::RgbaImage {
+    let size = data.size();
+    image::RgbaImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        let color: Rgba<f32> = data[vec2(x as usize, size.y - 1 - y as usize)].convert();
+        let color = Rgba::new(
+            color.r * color.a + 1.0 - color.a,
+            color.g * color.a + 1.0 - color.a,
+            color.b * color.a + 1.0 - color.a,
+            1.0,
+        );
+        let color: Rgba<u8> = color.convert();
+        image::Rgba([color.r, color.g, color.b, color.a])
+    })
+}
+
+pub fn run(canvas: &str, options: Options) {
+    let temp = canvas_path(canvas).with_extension("timelapse");
+    if temp.exists() {
+        std::fs::remove_dir_all(&temp).expect("Failed to clean up");
+    }
+    std::fs::create_dir_all(&options.output).expect("Failed to create output dir");
+    let area = options.area;
+    let step = (options.step * 1000.0) as u64;
+    let layer_names = saved_layer_names(canvas);
+    let mut layers = HashMap::<String, texture::Infinite>::new();
+    let mut frame_count = 0;
+    let mut frames: Vec<image::RgbaImage> = Vec::new();
+    let mut last_frame = None;
+    let mut next_frame_time = None;
+    let mut save_frame = |layers: &mut HashMap<String, texture::Infinite>| {
+        let mut data =
+            Matrix::filled_with(area.size().map(|x| x as usize), Rgba::TRANSPARENT_BLACK);
+        for name in &layer_names {
+            if let Some(layer) = layers.get_mut(name) {
+                blend_layer(&mut data, &layer.get(area));
+            }
+        }
+        if last_frame.as_ref() == Some(&data) {
+            return;
+        }
+        let image = to_image(&data);
+        let path = options.output.join(format!("{:06}.png", frame_count));
+        image.save(&path).expect("Failed to save frame");
+        frame_count += 1;
+        if options.gif {
+            frames.push(image);
+        }
+        last_frame = Some(data);
+    };
+    for entry in oplog::read(oplog::path(canvas)) {
+        let next = *next_frame_time.get_or_insert(entry.time + step);
+        if entry.time >= next {
+            save_frame(&mut layers);
+            next_frame_time = Some(next + (entry.time - next) / step * step + step);
+        }
+        let update = match entry.update {
+            Update::Draw { layer, pixels } => Update::Draw {
+                layer,
+                pixels: pixels
+                    .into_iter()
+                    .filter(|pixel| area.contains(pixel.position))
+                    .collect(),
+            },
+        };
+        layers
+            .entry(update.layer().to_owned())
+            .or_insert_with_key(|layer| texture::Infinite::new(layer_path(&temp, layer)))
+            .update(update, &entry.author, entry.time / 1000);
+    }
+    save_frame(&mut layers);
+    drop(layers);
+    std::fs::remove_dir_all(&temp).expect("Failed to clean up");
+    info!("Saved {} frames into {:?}", frame_count, options.output);
+
+    if options.gif {
+        let path = options.output.join("timelapse.gif");
+        let file = std::fs::File::create(&path).expect("Failed to create gif");
+        let mut encoder = image::codecs::gif::GifEncoder::new(std::io::BufWriter::new(file));
+        encoder
+            .set_repeat(image::codecs::gif::Repeat::Infinite)
+            .expect("Failed to encode gif");
+        for frame in frames {
+            encoder
+                .encode_frame(image::Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    image::Delay::from_numer_denom_ms(100, 1),
+                ))
+                .expect("Failed to encode gif");
+        }
+        info!("Saved {:?}", path);
+    }
+}
@@ -0,0 +1,46 @@
+use super::*;
+
+// Every non-empty line of the file is `<name> <token> [groups...]`, lines starting with # are ignored.
+// The file is reread on every login so users can be added without restarting the server.
+pub struct Users {
+    path: std::path::PathBuf,
+}
+
+impl Users {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+    pub fn authenticate(&self, token: &str) -> Option<User> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                error!("Failed to read users file {:?}: {}", self.path, e);
+                return None;
+            }
+        };
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap();
+            let user_token = match parts.next() {
+                Some(token) => token,
+                None => {
+                    warn!("User {:?} has no token", name);
+                    continue;
+                }
+            };
+            if user_token == token {
+                return Some(User {
+                    name: name.to_owned(),
+                    groups: parts.map(|group| group.to_owned()).collect(),
+                });
+            }
+        }
+        None
+    }
+}